@@ -1,5 +1,7 @@
 extern crate proc_macro;
-use proc_macro2::{Delimiter, Group, Literal, Punct, Spacing, TokenStream, TokenTree};
+use proc_macro2::{
+    Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree,
+};
 use proc_macro_error::proc_macro_error;
 use proc_macro_error::{abort, emit_error};
 use quote::quote;
@@ -113,73 +115,152 @@ fn parse_keycode_group(input: TokenStream, out: &mut TokenStream) {
 }
 
 fn punctuation_to_keycode(p: &Punct, out: &mut TokenStream) {
-    match p.as_char() {
-        // Normal punctuation
-        '-' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Minus), }),
-        '=' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Equal), }),
-        ';' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::SColon), }),
-        ',' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Comma), }),
-        '.' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Dot), }),
-        '/' => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Slash), }),
-
-        // Shifted punctuation
-        '!' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb1].as_slice()), }),
-        '@' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb2].as_slice()), }),
-        '#' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb3].as_slice()), }),
-        '$' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb4].as_slice()), }),
-        '%' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb5].as_slice()), }),
-        '^' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb6].as_slice()), }),
-        '&' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb7].as_slice()), }),
-        '*' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb8].as_slice()), }),
-        '_' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Minus].as_slice()), }),
-        '+' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Equal].as_slice()), }),
-        '|' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Bslash].as_slice()), }),
-        '~' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Grave].as_slice()), }),
-        '<' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Comma].as_slice()), }),
-        '>' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Dot].as_slice()), }),
-        '?' => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Slash].as_slice()), }),
+    match char_to_keycode(p.as_char()) {
+        Some((keycode, shifted)) => out.extend(keycode_to_action(&keycode, shifted)),
         // Is this reachable?
-        _ => emit_error!(p, "Punctuation could not be parsed as a keycode")
+        None => emit_error!(p, "Punctuation could not be parsed as a keycode"),
     }
 }
 
 fn literal_to_keycode(l: &Literal, out: &mut TokenStream) {
-    //let repr = l.to_string();
-    match l.to_string().as_str() {
-        "1" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb1), }),
-        "2" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb2), }),
-        "3" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb3), }),
-        "4" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb4), }),
-        "5" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb5), }),
-        "6" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb6), }),
-        "7" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb7), }),
-        "8" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb8), }),
-        "9" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb9), }),
-        "0" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Kb0), }),
+    let repr = l.to_string();
+    match repr.as_str() {
+        s if s.len() == 1 && s.as_bytes()[0].is_ascii_digit() => {
+            let (keycode, shifted) = char_to_keycode(s.as_bytes()[0] as char).unwrap();
+            out.extend(keycode_to_action(&keycode, shifted));
+        }
 
         // Char literals; mostly punctuation which can't be properly tokenized alone
-        r#"'\''"# => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Quote), }),
-        r#"'\\'"# => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Bslash), }),
-        // Shifted characters
-        "'['" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::LBracket), }),
-        "']'" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::RBracket), }),
-        "'`'" => out.extend(quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::Grave), }),
-        "'\"'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Quote].as_slice()), }),
-        "'('" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb9].as_slice()), }),
-        "')'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Kb0].as_slice()), }),
-        "'{'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::LBracket].as_slice()), }),
-        "'}'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::RBracket].as_slice()), }),
-        "'_'" => out.extend(quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::Minus].as_slice()), }),
-
-        s if s.starts_with('\'') => emit_error!(l, "Literal could not be parsed as a keycode"; help = "Maybe try without quotes?"),
-
-        s if s.starts_with('\"')  => {
-            if s.len() == 3 {
-                emit_error!(l, "Typing strings on key press is not yet supported"; help = "Did you mean to use apostrophes instead of quotes?");
-            } else {
-                emit_error!(l, "Typing strings on key press is not yet supported");
+        s if s.starts_with('\'') => {
+            let c = unescape(&s[1..s.len() - 1]).and_then(|s| {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => char_to_keycode(c),
+                    _ => None,
+                }
+            });
+            match c {
+                Some((keycode, shifted)) => out.extend(keycode_to_action(&keycode, shifted)),
+                None => emit_error!(l, "Literal could not be parsed as a keycode"; help = "Maybe try without quotes?"),
             }
         }
+
+        // String literals type the string
+        s if s.starts_with('"') => match unescape(&s[1..s.len() - 1]) {
+            Some(string) => string_to_sequence(l, &string, out),
+            None => emit_error!(l, "Unsupported escape sequence in string"; help = "Only \\n, \\t, \\\\, \\' and \\\" are supported"),
+        },
         _ => emit_error!(l, "Literal could not be parsed as a keycode")
     }
 }
+
+/// Generates an `Action::Sequence` typing the given string.
+fn string_to_sequence(l: &Literal, string: &str, out: &mut TokenStream) {
+    let mut events = TokenStream::new();
+    let mut shift = false;
+    for c in string.chars() {
+        let (keycode, shifted) = match char_to_keycode(c) {
+            Some(k) => k,
+            None => {
+                emit_error!(l, "Character {:?} cannot be typed on a US layout", c);
+                return;
+            }
+        };
+        if shifted && !shift {
+            events.extend(quote! { keyberon::action::SequenceEvent::Press(keyberon::key_code::KeyCode::LShift), });
+        } else if !shifted && shift {
+            events.extend(quote! { keyberon::action::SequenceEvent::Release(keyberon::key_code::KeyCode::LShift), });
+        }
+        shift = shifted;
+        events.extend(quote! { keyberon::action::SequenceEvent::Tap(keyberon::key_code::KeyCode::#keycode), });
+    }
+    if shift {
+        events.extend(quote! { keyberon::action::SequenceEvent::Release(keyberon::key_code::KeyCode::LShift), });
+    }
+    out.extend(quote! { keyberon::action::Action::Sequence(&[#events].as_slice()), });
+}
+
+/// Generates the action pressing the given keycode, with `LShift` if `shifted`.
+fn keycode_to_action(keycode: &Ident, shifted: bool) -> TokenStream {
+    if shifted {
+        quote! { keyberon::action::Action::MultipleKeyCodes(&[keyberon::key_code::KeyCode::LShift, keyberon::key_code::KeyCode::#keycode].as_slice()), }
+    } else {
+        quote! { keyberon::action::Action::KeyCode(keyberon::key_code::KeyCode::#keycode), }
+    }
+}
+
+/// Resolves the escape sequences of the content of a char or string literal.
+///
+/// Returns `None` for unsupported escapes.
+fn unescape(s: &str) -> Option<String> {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        res.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            c @ '\\' | c @ '\'' | c @ '"' => c,
+            _ => return None,
+        });
+    }
+    Some(res)
+}
+
+/// Returns the keycode typing the given character on a US layout,
+/// and if `LShift` must be pressed with it.
+fn char_to_keycode(c: char) -> Option<(Ident, bool)> {
+    let (keycode, shifted) = match c {
+        'a'..='z' => return Some((ident(&c.to_ascii_uppercase().to_string()), false)),
+        'A'..='Z' => return Some((ident(&c.to_string()), true)),
+        '0'..='9' => return Some((ident(&format!("Kb{}", c)), false)),
+
+        // Normal characters
+        ' ' => ("Space", false),
+        '\n' => ("Enter", false),
+        '\t' => ("Tab", false),
+        '-' => ("Minus", false),
+        '=' => ("Equal", false),
+        ';' => ("SColon", false),
+        ',' => ("Comma", false),
+        '.' => ("Dot", false),
+        '/' => ("Slash", false),
+        '\'' => ("Quote", false),
+        '\\' => ("Bslash", false),
+        '[' => ("LBracket", false),
+        ']' => ("RBracket", false),
+        '`' => ("Grave", false),
+
+        // Shifted characters
+        '!' => ("Kb1", true),
+        '@' => ("Kb2", true),
+        '#' => ("Kb3", true),
+        '$' => ("Kb4", true),
+        '%' => ("Kb5", true),
+        '^' => ("Kb6", true),
+        '&' => ("Kb7", true),
+        '*' => ("Kb8", true),
+        '(' => ("Kb9", true),
+        ')' => ("Kb0", true),
+        '_' => ("Minus", true),
+        '+' => ("Equal", true),
+        ':' => ("SColon", true),
+        '<' => ("Comma", true),
+        '>' => ("Dot", true),
+        '?' => ("Slash", true),
+        '"' => ("Quote", true),
+        '|' => ("Bslash", true),
+        '{' => ("LBracket", true),
+        '}' => ("RBracket", true),
+        '~' => ("Grave", true),
+        _ => return None,
+    };
+    Some((ident(keycode), shifted))
+}
+
+fn ident(s: &str) -> Ident {
+    Ident::new(s, Span::call_site())
+}
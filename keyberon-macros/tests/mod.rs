@@ -1,5 +1,5 @@
 extern crate keyberon_macros;
-use keyberon::action::{k, l, m, Action, Action::*, HoldTapConfig, HoldTapAction};
+use keyberon::action::{k, l, m, Action, Action::*, HoldTapConfig, HoldTapAction, SequenceEvent};
 use keyberon::key_code::KeyCode::*;
 use keyberon::layout::*;
 use keyberon_macros::layout;
@@ -18,6 +18,8 @@ fn test_layout_equality() {
         tap: Action::KeyCode(Enter),
         config: HoldTapConfig::PermissiveHold,
         tap_hold_interval: 0,
+        require_prior_idle: 0,
+        retro_tap: false,
     });
 
     #[rustfmt::skip]
@@ -108,3 +110,35 @@ fn test_keycode_group_comma() {
     static B: Layers<3, 1, 1> = [[[k(C), Action::MultipleActions(&[k(D), k(E)].as_slice()), k(F)]]];
     assert_eq!(A, B);
 }
+
+#[test]
+fn test_string() {
+    static STR: Layers<3, 1, 1> = layout! {
+        {
+            [ "Hi!" "a\n" ['"' "ok"] ]
+        }
+    };
+    static SEQ: Layers<3, 1, 1> = [[[
+        Action::Sequence(
+            &[
+                SequenceEvent::Press(LShift),
+                SequenceEvent::Tap(H),
+                SequenceEvent::Release(LShift),
+                SequenceEvent::Tap(I),
+                SequenceEvent::Press(LShift),
+                SequenceEvent::Tap(Kb1),
+                SequenceEvent::Release(LShift),
+            ]
+            .as_slice(),
+        ),
+        Action::Sequence(&[SequenceEvent::Tap(A), SequenceEvent::Tap(Enter)].as_slice()),
+        Action::MultipleActions(
+            &[
+                m(&[LShift, Quote].as_slice()),
+                Action::Sequence(&[SequenceEvent::Tap(O), SequenceEvent::Tap(K)].as_slice()),
+            ]
+            .as_slice(),
+        ),
+    ]]];
+    assert_eq!(STR, SEQ);
+}
@@ -1,7 +1,10 @@
 //! The different actions that can be done.
 
+use crate::consumer::ConsumerCode;
 use crate::key_code::KeyCode;
 use crate::layout::{StackedIter, WaitingAction};
+use crate::mouse::MouseKey;
+use crate::system::SystemCode;
 use core::fmt::Debug;
 
 /// Behavior configuration of HoldTap.
@@ -10,13 +13,15 @@ use core::fmt::Debug;
 pub enum HoldTapConfig {
     /// Only the timeout will determine between hold and tap action.
     ///
-    /// This is a sane default.
+    /// This is a sane default. This is the "tap-preferred" flavor of
+    /// ZMK.
     Default,
     /// If there is a key press, the hold action is activated.
     ///
     /// This behavior is interesting for a key which the tap action is
     /// not used in the flow of typing, like escape for example. If
     /// you are annoyed by accidental tap, you can try this behavior.
+    /// This is the "hold-preferred" flavor of ZMK.
     HoldOnOtherKeyPress,
     /// If there is a press and release of another key, the hold
     /// action is activated.
@@ -24,7 +29,8 @@ pub enum HoldTapConfig {
     /// This behavior is interesting for fast typist: the different
     /// between hold and tap would more be based on the sequence of
     /// events than on timing. Be aware that doing the good succession
-    /// of key might require some training.
+    /// of key might require some training. This is the "balanced"
+    /// flavor of ZMK.
     PermissiveHold,
     /// A custom configuration. Allows the behavior to be controlled by a caller
     /// supplied handler function.
@@ -77,6 +83,8 @@ pub enum HoldTapConfig {
     ///     tap: Action::KeyCode(KeyCode::A),
     ///     config: HoldTapConfig::Custom(left_mod),
     ///     tap_hold_interval: 0,
+    ///     require_prior_idle: 0,
+    ///     retro_tap: false,
     /// });
     ///
     /// // Assuming a standard QWERTY layout, the right shift hold action will
@@ -87,6 +95,8 @@ pub enum HoldTapConfig {
     ///     tap: Action::KeyCode(KeyCode::SColon),
     ///     config: HoldTapConfig::Custom(right_mod),
     ///     tap_hold_interval: 0,
+    ///     require_prior_idle: 0,
+    ///     retro_tap: false,
     /// });
     /// ```
     Custom(fn(StackedIter) -> Option<WaitingAction>),
@@ -113,7 +123,9 @@ impl PartialEq for HoldTapConfig {
             | (HoldTapConfig::HoldOnOtherKeyPress, HoldTapConfig::HoldOnOtherKeyPress)
             | (HoldTapConfig::PermissiveHold, HoldTapConfig::PermissiveHold) => true,
             (HoldTapConfig::Custom(self_func), HoldTapConfig::Custom(other_func)) => {
-                *self_func as fn(StackedIter<'static>) -> Option<WaitingAction> == *other_func
+                // Compared as addresses, `core::ptr::fn_addr_eq` needing
+                // Rust 1.85.
+                *self_func as usize == *other_func as usize
             }
             _ => false,
         }
@@ -165,6 +177,217 @@ where
     ///
     /// To deactivate the functionality, set this to 0.
     pub tap_hold_interval: u16,
+    /// If the key is pressed less than `require_prior_idle` ticks
+    /// after the previous press of a key that is not a modifier, the
+    /// tap action is directly performed. This avoids accidental holds
+    /// while typing fast, typically with home row modifiers.
+    ///
+    /// To deactivate the functionality, set this to 0.
+    pub require_prior_idle: u16,
+    /// If the key is held more than `timeout` ticks, and released
+    /// without any other key pressed meanwhile, the tap action is
+    /// performed after the release of the hold action.
+    pub retro_tap: bool,
+}
+
+impl<T> HoldTapAction<T> {
+    /// Creates a `HoldTapAction` with the given timeout, hold and tap
+    /// actions, using `HoldTapConfig::Default` and with the other
+    /// features deactivated.
+    ///
+    /// The other fields can be given with the struct update syntax,
+    /// keeping the definition valid when new fields are added:
+    ///
+    /// ```
+    /// use keyberon::action::{k, Action, HoldTapAction, HoldTapConfig};
+    /// use keyberon::key_code::KeyCode;
+    ///
+    /// const A_SHIFT: Action = Action::HoldTap(&HoldTapAction {
+    ///     config: HoldTapConfig::PermissiveHold,
+    ///     ..HoldTapAction::new(200, k(KeyCode::LShift), k(KeyCode::A))
+    /// });
+    /// ```
+    pub const fn new(timeout: u16, hold: Action<T>, tap: Action<T>) -> Self {
+        HoldTapAction {
+            timeout,
+            hold,
+            tap,
+            config: HoldTapConfig::Default,
+            tap_hold_interval: 0,
+            require_prior_idle: 0,
+            retro_tap: false,
+        }
+    }
+}
+
+/// Perform different actions depending on the number of consecutive
+/// taps of a key.
+///
+/// The key is tapped several times in a row, each tap happening less
+/// than `timeout` ticks after the previous press or release of the
+/// key. When the timeout is elapsed, or when another key is pressed,
+/// the action corresponding to the number of taps is performed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TapDanceAction<T>
+where
+    T: 'static,
+{
+    /// The duration, in ticks (usually milliseconds), after which
+    /// the tap dance ends if the key is not pressed or released
+    /// again.
+    pub timeout: u16,
+    /// The actions corresponding to the number of taps: the first
+    /// action for one tap, the second for two taps, and so on. If the
+    /// key is tapped more times than there are actions, the last
+    /// action is performed.
+    ///
+    /// The action is held while the key is held: if the key is
+    /// pressed at the end of the tap dance, the action is released
+    /// with the key, else it is released just after being performed.
+    pub actions: &'static [Action<T>],
+    /// If set, the action performed if the key is held at the end of
+    /// the tap dance, whatever the number of taps.
+    pub hold: Option<Action<T>>,
+}
+
+/// Keep an action active until the next key press.
+///
+/// When the key is tapped, `action` is activated and stays active
+/// until another key (that is not a modifier) is pressed. The action
+/// is then released just after this key press is taken into account.
+/// Mostly used with a modifier, to avoid holding it. Pressing the key
+/// again while the action is waiting for the next key press cancels
+/// it.
+///
+/// If the key is held while another key is pressed, it behaves like
+/// a classic key: the action is released with the key.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OneShotAction<T>
+where
+    T: 'static,
+{
+    /// The action activated by the one shot key.
+    pub action: Action<T>,
+    /// The duration, in ticks (usually milliseconds), during which
+    /// the action is kept active after the release of the key. When
+    /// this duration is elapsed without any other key press, the
+    /// action is released.
+    ///
+    /// To deactivate the timeout, set this to 0.
+    pub timeout: u16,
+}
+
+/// Activate a layer for the next key press.
+///
+/// When the key is tapped, `layer` is activated for the next key
+/// press, and deactivated when this key is released. Useful for a
+/// layer that is only needed for one character at a time. Pressing
+/// the key again while the layer is waiting for the next key press
+/// cancels it.
+///
+/// If the key is held while another key is pressed, it behaves like
+/// [`Action::Layer`]: the layer is active while the key is held.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct OneShotLayerAction {
+    /// The layer activated by the one shot key.
+    pub layer: usize,
+    /// The duration, in ticks (usually milliseconds), during which
+    /// the layer is kept active after the release of the key. When
+    /// this duration is elapsed without any other key press, the
+    /// layer is deactivated.
+    ///
+    /// To deactivate the timeout, set this to 0.
+    pub timeout: u16,
+}
+
+/// Shift the letters until the end of the current word (see
+/// [`Action::CapsWord`]).
+///
+/// While caps word is active, `LShift` is added to the key codes
+/// when the last pressed key is a letter or one of the `shifted` key
+/// codes, so that a rollover with a digit doesn't shift it. Caps
+/// word is deactivated when a key code that is not a letter, a
+/// modifier, or one of the `shifted` and `continuing` key codes is
+/// pressed, or when no key is pressed during `timeout` ticks.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CapsWordConfig {
+    /// The duration, in ticks (usually milliseconds), without key
+    /// press after which caps word is deactivated.
+    ///
+    /// To deactivate the timeout, set this to 0.
+    pub timeout: u16,
+    /// The key codes, other than the letters, that are shifted, as
+    /// `Minus` to type underscores.
+    pub shifted: &'static [KeyCode],
+    /// The key codes that continue the word without being shifted,
+    /// as digits or `BSpace`.
+    pub continuing: &'static [KeyCode],
+}
+
+/// Perform an action by typing a sequence of keys after a leader key
+/// (see [`Action::Leader`]).
+///
+/// After the leader key is pressed, the key codes (that are not
+/// modifiers) pressed are captured instead of being sent. As soon as
+/// the captured key codes are equal to one of the `sequences`, the
+/// corresponding action is performed, and released with the last key
+/// of the sequence. If a sequence is the prefix of another one, the
+/// longer one can thus never be typed.
+///
+/// If the captured key codes are not the beginning of any of the
+/// `sequences`, or if no key is pressed during `timeout` ticks, the
+/// `failure` action is performed and released at the next tick.
+/// Pressing the leader key again cancels the capture.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LeaderConfig<T>
+where
+    T: 'static,
+{
+    /// The duration, in ticks (usually milliseconds), without key
+    /// press after which the capture fails.
+    ///
+    /// To deactivate the timeout, set this to 0.
+    pub timeout: u16,
+    /// The sequences of key codes, and the corresponding actions.
+    /// The sequences can't be longer than 8 key codes.
+    pub sequences: &'static [(&'static [KeyCode], Action<T>)],
+    /// The action performed when the capture fails. Use
+    /// [`Action::NoOp`] to ignore failures, or an [`Action::Custom`] to
+    /// be notified by a [`CustomEvent`](crate::layout::CustomEvent).
+    pub failure: Action<T>,
+}
+
+/// Perform a different action when some modifiers are held (see
+/// [`Action::ModMorph`]).
+///
+/// If one of `mods` is held when the key is pressed, `morphed` is
+/// performed, and the held `mods` are removed from the key codes
+/// while the key is held. Else, `default` is performed. For example,
+/// Shift+BSpace can send Delete.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ModMorphAction<T>
+where
+    T: 'static,
+{
+    /// The modifiers triggering the morphed action.
+    pub mods: &'static [KeyCode],
+    /// The action performed when none of the modifiers is held.
+    pub default: Action<T>,
+    /// The action performed when one of the modifiers is held.
+    pub morphed: Action<T>,
+}
+
+/// An event of a sequence of key codes (see [`Action::Sequence`]).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SequenceEvent {
+    /// Press the given key code.
+    Press(KeyCode),
+    /// Release the given key code.
+    Release(KeyCode),
+    /// Press the given key code, and release it at the next tick.
+    Tap(KeyCode),
+    /// Wait the given number of ticks (usually milliseconds).
+    Delay(u16),
 }
 
 /// The different actions that can be done.
@@ -177,7 +400,9 @@ where
     /// No operation action: just do nothing.
     NoOp,
     /// Transparent, i.e. get the action from the default layer. On
-    /// the default layer, it is equivalent to `NoOp`.
+    /// the default layer, it is equivalent to `NoOp`. With
+    /// [`LayerMode::Stacked`](crate::layout::LayerMode::Stacked), get
+    /// the action from the next lower active layer instead.
     Trans,
     /// A key code, i.e. a classic key.
     KeyCode(KeyCode),
@@ -187,14 +412,86 @@ where
     MultipleKeyCodes(&'static &'static [KeyCode]),
     /// Multiple actions sent at the same time.
     MultipleActions(&'static &'static [Action<T>]),
+    /// A sequence of key code events, played one event per tick
+    /// when the key is pressed. Useful to type a word or a snippet.
+    ///
+    /// The key codes pressed by the sequence are independent of the
+    /// keys physically pressed, and the ones that are still pressed
+    /// at the end of the sequence are released. Note that the
+    /// modifiers physically held are not removed: holding `LCtrl`
+    /// while the sequence types `A` sends Ctrl+A. If a sequence is
+    /// triggered while another one is playing, it is played after
+    /// it.
+    Sequence(&'static &'static [SequenceEvent]),
     /// While pressed, change the current layer. That's the classic
     /// Fn key. If several layer actions are hold at the same time,
     /// the last pressed defines the current layer.
     Layer(usize),
     /// Change the default layer.
     DefaultLayer(usize),
+    /// Toggle a layer: activate it if it is not toggled, deactivate
+    /// it otherwise. A toggled layer stays active until toggled
+    /// again. If several layers are toggled, the last toggled defines
+    /// the current layer, but a layer activated by a held
+    /// [`Action::Layer`] takes precedence.
+    ToggleLayer(usize),
+    /// Switch to a layer: deactivate all the layers (except the
+    /// default layer), held ones included, and toggle the given layer.
+    ToLayer(usize),
+    /// Lock the current layer: the current layer is toggled, and
+    /// thus stays active when the key activating it is released. If
+    /// the current layer is already locked, it is unlocked.
+    LayerLock,
     /// Perform different actions on key hold/tap (see [`HoldTapAction`]).
     HoldTap(&'static HoldTapAction<T>),
+    /// Perform different actions depending on the number of
+    /// consecutive taps (see [`TapDanceAction`]).
+    TapDance(&'static TapDanceAction<T>),
+    /// Keep an action active until the next key press (see
+    /// [`OneShotAction`]).
+    OneShot(&'static OneShotAction<T>),
+    /// Activate a layer for the next key press (see
+    /// [`OneShotLayerAction`]).
+    OneShotLayer(&'static OneShotLayerAction),
+    /// Toggle caps word: shift the letters until the end of the
+    /// current word (see [`CapsWordConfig`]).
+    CapsWord(&'static CapsWordConfig),
+    /// Start capturing the following key presses to perform the
+    /// action of a sequence (see [`LeaderConfig`]).
+    Leader(&'static LeaderConfig<T>),
+    /// Perform a different action when some modifiers are held (see
+    /// [`ModMorphAction`]).
+    ModMorph(&'static ModMorphAction<T>),
+    /// Repeat the last key code that is not a modifier, with the
+    /// modifiers that were active when it was pressed.
+    Repeat,
+    /// Send the counterpart of the last key code that is not a
+    /// modifier, with the modifiers that were active when it was
+    /// pressed. The counterparts are given as `(last, counterpart)`
+    /// pairs, as `(Up, Down)`. If the last key code has no
+    /// counterpart, nothing is sent.
+    AltRepeat(&'static &'static [(KeyCode, KeyCode)]),
+    /// Start recording the given dynamic macro slot. If a dynamic
+    /// macro is being recorded, stop recording instead. The key code
+    /// changes are recorded with the time between them. See
+    /// [`Layout::recording_dynamic_macro`](crate::layout::Layout::recording_dynamic_macro).
+    DynamicMacroRecord(usize),
+    /// Stop recording the dynamic macro.
+    DynamicMacroStop,
+    /// Play the given dynamic macro slot, as an [`Action::Sequence`].
+    DynamicMacroPlay(usize),
+    /// A consumer control code, i.e. a media key, sent with a
+    /// [`ConsumerControl`](crate::consumer::ConsumerControl) device.
+    /// See [`Layout::consumer_codes`](crate::layout::Layout::consumer_codes).
+    Consumer(ConsumerCode),
+    /// A system control code (power down, sleep, wake up), sent with
+    /// a [`SystemControl`](crate::system::SystemControl) device. See
+    /// [`Layout::system_codes`](crate::layout::Layout::system_codes).
+    System(SystemCode),
+    /// A mouse key: a mouse button, a cursor movement or a wheel
+    /// scroll, sent with a [`Mouse`](crate::mouse::Mouse) device. See
+    /// [`Layout::mouse_report`](crate::layout::Layout::mouse_report).
+    Mouse(MouseKey),
     /// Custom action.
     ///
     /// Define a user defined action. This enum can be anything you
@@ -102,7 +102,7 @@ impl Chord {
 
     fn contains_chord(&mut self, events: &[Event]) -> bool {
         for key in self.def.1 {
-            if !events.iter().any(|&k| (&k.coord() == key && k.is_press())) {
+            if !events.iter().any(|&k| &k.coord() == key && k.is_press()) {
                 return false;
             }
         }
@@ -114,7 +114,7 @@ impl Chord {
         for key in self.def.1 {
             if let Some(position) = events
                 .iter()
-                .position(|&k| (&k.coord() == key && k.is_press()))
+                .position(|&k| &k.coord() == key && k.is_press())
             {
                 events.swap_remove(position);
             }
@@ -129,7 +129,7 @@ impl Chord {
             for key in self.def.1 {
                 if let Some(position) = events
                     .iter()
-                    .position(|&k| (&k.coord() == key && k.is_release()))
+                    .position(|&k| &k.coord() == key && k.is_release())
                 {
                     events.swap_remove(position);
                 }
@@ -0,0 +1,253 @@
+//! Consumer control HID device implementation.
+//!
+//! The media keys (volume, play/pause, brightness, browser keys...)
+//! are sent on the consumer usage page, using a [`ConsumerControl`]
+//! HID device alongside the [`Keyboard`](crate::keyboard::Keyboard).
+//! The [`Layout`](crate::layout::Layout) gives the pressed consumer
+//! codes with
+//! [`Layout::consumer_codes`](crate::layout::Layout::consumer_codes).
+
+use crate::hid::{self, HidDevice, Protocol, ReportType, Subclass};
+use crate::key_code::KeyCode;
+
+/// A consumer control code, i.e. a usage of the consumer usage
+/// page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u16)]
+pub enum ConsumerCode {
+    /// Sleep.
+    Sleep = 0x32,
+    /// Display Brightness Increment.
+    BrightnessUp = 0x6F,
+    /// Display Brightness Decrement.
+    BrightnessDown = 0x70,
+    /// Fast Forward.
+    FastForward = 0xB3,
+    /// Rewind.
+    Rewind = 0xB4,
+    /// Scan Next Track.
+    NextTrack = 0xB5,
+    /// Scan Previous Track.
+    PreviousTrack = 0xB6,
+    /// Stop.
+    Stop = 0xB7,
+    /// Eject.
+    Eject = 0xB8,
+    /// Play/Pause.
+    PlayPause = 0xCD,
+    /// Mute.
+    Mute = 0xE2,
+    /// Volume Increment.
+    VolumeUp = 0xE9,
+    /// Volume Decrement.
+    VolumeDown = 0xEA,
+    /// AL Text Editor.
+    TextEditor = 0x185,
+    /// AL Email Reader.
+    Mail = 0x18A,
+    /// AL Calculator.
+    Calculator = 0x192,
+    /// AL Local Machine Browser.
+    MyComputer = 0x194,
+    /// AL Internet Browser.
+    Browser = 0x196,
+    /// AL Terminal Lock/Screensaver.
+    LockScreen = 0x19E,
+    /// AC Search.
+    BrowserSearch = 0x221,
+    /// AC Home.
+    BrowserHome = 0x223,
+    /// AC Back.
+    BrowserBack = 0x224,
+    /// AC Forward.
+    BrowserForward = 0x225,
+    /// AC Stop.
+    BrowserStop = 0x226,
+    /// AC Refresh.
+    BrowserRefresh = 0x227,
+    /// AC Bookmarks.
+    BrowserBookmarks = 0x22A,
+    /// AC Scroll Up.
+    ScrollUp = 0x233,
+    /// AC Scroll Down.
+    ScrollDown = 0x234,
+}
+
+impl ConsumerCode {
+    /// Returns the consumer code corresponding to an unofficial
+    /// `KeyCode::Media*` key code, if any.
+    ///
+    /// The layout sends these key codes as consumer codes.
+    pub fn from_keycode(kc: KeyCode) -> Option<Self> {
+        use ConsumerCode::*;
+        Some(match kc {
+            KeyCode::MediaPlayPause => PlayPause,
+            KeyCode::MediaStopCD => Stop,
+            KeyCode::MediaPreviousSong => PreviousTrack,
+            KeyCode::MediaNextSong => NextTrack,
+            KeyCode::MediaEjectCD => Eject,
+            KeyCode::MediaVolUp => VolumeUp,
+            KeyCode::MediaVolDown => VolumeDown,
+            KeyCode::MediaMute => Mute,
+            KeyCode::MediaWWW => Browser,
+            KeyCode::MediaBack => BrowserBack,
+            KeyCode::MediaForward => BrowserForward,
+            KeyCode::MediaStop => BrowserStop,
+            KeyCode::MediaFind => BrowserSearch,
+            KeyCode::MediaScrollUp => ScrollUp,
+            KeyCode::MediaScrollDown => ScrollDown,
+            KeyCode::MediaEdit => TextEditor,
+            KeyCode::MediaSleep => Sleep,
+            KeyCode::MediaCoffee => LockScreen,
+            KeyCode::MediaRefresh => BrowserRefresh,
+            KeyCode::MediaCalc => Calculator,
+            _ => return None,
+        })
+    }
+}
+
+/// The number of consumer codes in a [`ConsumerReport`].
+const REPORT_CODES: usize = 4;
+
+/// A consumer control USB HID report.
+///
+/// It can handle 4 consumer codes.
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct ConsumerReport([u8; 2 * REPORT_CODES]);
+
+impl core::iter::FromIterator<ConsumerCode> for ConsumerReport {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = ConsumerCode>,
+    {
+        let mut res = Self::default();
+        for cc in iter {
+            res.pressed(cc);
+        }
+        res
+    }
+}
+
+impl ConsumerReport {
+    /// Returns the byte slice corresponding to the report.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Add the given consumer code to the report. If the report is
+    /// full, the code is ignored.
+    pub fn pressed(&mut self, cc: ConsumerCode) {
+        let code = (cc as u16).to_le_bytes();
+        if self.0.chunks(2).any(|c| c == code) {
+            return;
+        }
+        if let Some(c) = self.0.chunks_mut(2).find(|c| c == &[0, 0]) {
+            c.copy_from_slice(&code);
+        }
+    }
+}
+
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0C,        // Usage Page (Consumer)
+    0x09, 0x01,        // Usage (Consumer Control)
+    0xA1, 0x01,        // Collection (Application)
+    0x19, 0x00,        //   Usage Minimum (Unassigned)
+    0x2A, 0xFF, 0x03,  //   Usage Maximum (0x3FF)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x26, 0xFF, 0x03,  //   Logical Maximum (1023)
+    0x95, 0x04,        //   Report Count (4)
+    0x75, 0x10,        //   Report Size (16)
+    0x81, 0x00,        //   Input (Data,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0xC0,              // End Collection
+];
+
+/// A consumer control HID device.
+#[derive(Default)]
+pub struct ConsumerControl {
+    report: ConsumerReport,
+}
+
+impl ConsumerControl {
+    /// Creates a new `ConsumerControl` object.
+    pub fn new() -> ConsumerControl {
+        ConsumerControl::default()
+    }
+
+    /// Set the current consumer HID report.  Returns `true` if it is
+    /// modified.
+    pub fn set_consumer_report(&mut self, report: ConsumerReport) -> bool {
+        if report == self.report {
+            false
+        } else {
+            self.report = report;
+            true
+        }
+    }
+}
+
+impl HidDevice for ConsumerControl {
+    fn subclass(&self) -> Subclass {
+        Subclass::None
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::None
+    }
+
+    fn max_packet_size(&self) -> u16 {
+        8
+    }
+
+    fn report_descriptor(&self) -> &[u8] {
+        REPORT_DESCRIPTOR
+    }
+
+    fn get_report(&mut self, report_type: ReportType, _report_id: u8) -> Result<&[u8], hid::Error> {
+        match report_type {
+            ReportType::Input => Ok(self.report.as_bytes()),
+            _ => Err(hid::Error),
+        }
+    }
+
+    fn set_report(
+        &mut self,
+        _report_type: ReportType,
+        _report_id: u8,
+        _data: &[u8],
+    ) -> Result<(), hid::Error> {
+        Err(hid::Error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut consumer = ConsumerControl::new();
+        assert_eq!(consumer.report.as_bytes(), &[0; 8]);
+        let report: ConsumerReport = [ConsumerCode::VolumeUp, ConsumerCode::BrowserHome]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(report.as_bytes(), &[0xE9, 0, 0x23, 0x02, 0, 0, 0, 0]);
+        assert!(consumer.set_consumer_report(report.clone()));
+        assert!(!consumer.set_consumer_report(report));
+
+        // duplicated and extra codes are ignored
+        let report: ConsumerReport = [
+            ConsumerCode::Mute,
+            ConsumerCode::Mute,
+            ConsumerCode::Stop,
+            ConsumerCode::Eject,
+            ConsumerCode::Sleep,
+            ConsumerCode::Calculator,
+        ]
+        .iter()
+        .copied()
+        .collect();
+        assert_eq!(report.as_bytes(), &[0xE2, 0, 0xB7, 0, 0xB8, 0, 0x32, 0]);
+    }
+}
@@ -0,0 +1,580 @@
+//! Compact binary encoding of the actions.
+//!
+//! An [`Action`] is built from `&'static` references, so it can't be
+//! stored in flash or sent to the host. This module defines a
+//! compact 16-bit encoding of the actions, close to the QMK one, and
+//! a versioned serialization format for the whole keymap, allowing
+//! to save and load a [`RuntimeKeymap`].
+//!
+//! The encoding of an action is:
+//!
+//! | Code              | Action                                     |
+//! |-------------------|--------------------------------------------|
+//! | `0x0000`          | [`Action::NoOp`], or `KeyCode::No`         |
+//! | `0x0001`          | [`Action::Trans`]                          |
+//! | `0x0004..=0x00FB` | [`Action::KeyCode`], the code being the HID usage |
+//! | `0x5200..=0x521F` | [`Action::ToLayer`]                        |
+//! | `0x5220..=0x523F` | [`Action::Layer`]                          |
+//! | `0x5240..=0x525F` | [`Action::DefaultLayer`]                   |
+//! | `0x5260..=0x527F` | [`Action::ToggleLayer`]                    |
+//! | `0x7C53..=0x7C54` | [`Action::DynamicMacroRecord`]             |
+//! | `0x7C55..=0x7C56` | [`Action::DynamicMacroPlay`]               |
+//! | `0x7C57`          | [`Action::DynamicMacroStop`]               |
+//! | `0x7C79`          | [`Action::Repeat`]                         |
+//! | `0x7C7B`          | [`Action::LayerLock`]                      |
+//! | `0x7E40..=0x7FFF` | a user action, see below                   |
+//!
+//! The other actions (hold tap, tap dance, custom actions...) can't
+//! be described in 16 bits. They are defined statically in a table
+//! of user actions, and encoded by their index in this table.
+//!
+//! A serialized keymap is made of a 4 bytes header (the version of
+//! the format, the number of layers, rows and columns), followed by
+//! the big endian encoding of each action, in layer, row and column
+//! order.
+
+use crate::action::Action;
+use crate::key_code::KeyCode;
+use crate::keymap::{Keymap, RuntimeKeymap};
+use core::convert::TryFrom;
+
+/// The version of the serialization format.
+pub const VERSION: u8 = 1;
+
+/// The size of the header of a serialized keymap.
+pub const HEADER_LEN: usize = 4;
+
+/// The maximum number of layers that can be encoded.
+pub const MAX_LAYERS: u8 = 32;
+
+/// The maximum number of user actions that can be encoded.
+pub const MAX_USER_ACTIONS: u16 = USER_LAST - USER + 1;
+
+const NO_OP: u16 = 0x0000;
+const TRANS: u16 = 0x0001;
+const TO_LAYER: u16 = 0x5200;
+const LAYER: u16 = 0x5220;
+const DEFAULT_LAYER: u16 = 0x5240;
+const TOGGLE_LAYER: u16 = 0x5260;
+const DYNAMIC_MACRO_RECORD: u16 = 0x7C53;
+const DYNAMIC_MACRO_PLAY: u16 = 0x7C55;
+const DYNAMIC_MACRO_STOP: u16 = 0x7C57;
+const REPEAT: u16 = 0x7C79;
+const LAYER_LOCK: u16 = 0x7C7B;
+const USER: u16 = 0x7E40;
+const USER_LAST: u16 = 0x7FFF;
+
+/// The number of dynamic macro slots.
+const DYNAMIC_MACROS: u8 = 2;
+
+// The actions must be written literally to be promoted to `'static`
+// references, as `T` is generic.
+macro_rules! layers {
+    ($variant:ident, $layer:expr) => {
+        layers!(@ $variant, $layer, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23
+            24 25 26 27 28 29 30 31)
+    };
+    (@ $variant:ident, $layer:expr, $($l:literal)*) => {
+        match $layer {
+            $($l => &Action::$variant($l),)*
+            _ => return Err(DecodeError::OutOfRange),
+        }
+    };
+}
+
+macro_rules! keycodes {
+    ($($kc:ident)*) => {
+        fn keycode(code: u8) -> Option<KeyCode> {
+            match code {
+                $(c if c == KeyCode::$kc as u8 => Some(KeyCode::$kc),)*
+                _ => None,
+            }
+        }
+
+        /// Returns a `'static` reference to the action of the given
+        /// key code.
+        pub(crate) fn keycode_action<T>(kc: KeyCode) -> &'static Action<T> {
+            match kc {
+                $(KeyCode::$kc => &Action::KeyCode(KeyCode::$kc),)*
+            }
+        }
+    };
+}
+
+keycodes! {
+    No ErrorRollOver PostFail ErrorUndefined A B C D E F G H I J K L M N O P Q R S T U V W X Y Z
+    Kb1 Kb2 Kb3 Kb4 Kb5 Kb6 Kb7 Kb8 Kb9 Kb0 Enter Escape BSpace Tab Space Minus Equal LBracket
+    RBracket Bslash NonUsHash SColon Quote Grave Comma Dot Slash CapsLock F1 F2 F3 F4 F5 F6 F7
+    F8 F9 F10 F11 F12 PScreen ScrollLock Pause Insert Home PgUp Delete End PgDown Right Left
+    Down Up NumLock KpSlash KpAsterisk KpMinus KpPlus KpEnter Kp1 Kp2 Kp3 Kp4 Kp5 Kp6 Kp7 Kp8
+    Kp9 Kp0 KpDot NonUsBslash Application Power KpEqual F13 F14 F15 F16 F17 F18 F19 F20 F21 F22
+    F23 F24 Execute Help Menu Select Stop Again Undo Cut Copy Paste Find Mute VolUp VolDown
+    LockingCapsLock LockingNumLock LockingScrollLock KpComma KpEqualSign Intl1 Intl2 Intl3 Intl4
+    Intl5 Intl6 Intl7 Intl8 Intl9 Lang1 Lang2 Lang3 Lang4 Lang5 Lang6 Lang7 Lang8 Lang9 AltErase
+    SysReq Cancel Clear Prior Return Separator Out Oper ClearAgain CrSel ExSel LCtrl LShift LAlt
+    LGui RCtrl RShift RAlt RGui MediaPlayPause MediaStopCD MediaPreviousSong MediaNextSong
+    MediaEjectCD MediaVolUp MediaVolDown MediaMute MediaWWW MediaBack MediaForward MediaStop
+    MediaFind MediaScrollUp MediaScrollDown MediaEdit MediaSleep MediaCoffee MediaRefresh
+    MediaCalc
+}
+
+/// An error while decoding an action or a keymap.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The code doesn't correspond to any action.
+    UnknownEncoding(u16),
+    /// The code is a user action that is not in the table of user
+    /// actions.
+    UnknownUserAction(u16),
+    /// The serialized keymap uses an unsupported version of the
+    /// format.
+    UnsupportedVersion(u8),
+    /// The serialized keymap doesn't have the number of layers, rows
+    /// and columns of the keymap.
+    InvalidDimensions,
+    /// The length of the serialized keymap is invalid.
+    InvalidLength,
+    /// The action code is out of the encodable range, as a layer
+    /// greater than [`MAX_LAYERS`].
+    OutOfRange,
+    /// The layer, row or column is out of the keymap.
+    InvalidKey,
+}
+
+/// An error while encoding a keymap.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EncodeError {
+    /// The action of the given layer, row and column can't be
+    /// encoded.
+    UnsupportedAction {
+        /// The layer of the action.
+        layer: usize,
+        /// The row of the action.
+        row: usize,
+        /// The column of the action.
+        col: usize,
+    },
+    /// The keymap has too many layers, rows or columns to be
+    /// serialized.
+    KeymapTooLarge,
+    /// The buffer is too small to contain the serialized keymap.
+    BufferTooSmall,
+}
+
+/// The runtime representation of an encoded action.
+///
+/// It is the result of decoding a 16-bit code, and can be converted
+/// to the corresponding [`Action`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ActionCode {
+    /// [`Action::NoOp`].
+    NoOp,
+    /// [`Action::Trans`].
+    Trans,
+    /// [`Action::KeyCode`].
+    KeyCode(KeyCode),
+    /// [`Action::Layer`].
+    Layer(u8),
+    /// [`Action::DefaultLayer`].
+    DefaultLayer(u8),
+    /// [`Action::ToggleLayer`].
+    ToggleLayer(u8),
+    /// [`Action::ToLayer`].
+    ToLayer(u8),
+    /// [`Action::LayerLock`].
+    LayerLock,
+    /// [`Action::Repeat`].
+    Repeat,
+    /// [`Action::DynamicMacroRecord`].
+    DynamicMacroRecord(u8),
+    /// [`Action::DynamicMacroStop`].
+    DynamicMacroStop,
+    /// [`Action::DynamicMacroPlay`].
+    DynamicMacroPlay(u8),
+    /// The action at the given index of the table of user actions.
+    User(u16),
+}
+
+impl ActionCode {
+    /// Decodes a 16-bit code.
+    ///
+    /// Returns [`DecodeError::UnknownEncoding`] if the code doesn't
+    /// correspond to any action.
+    pub fn decode(code: u16) -> Result<Self, DecodeError> {
+        let layer = |base: u16| (code - base) as u8;
+        Ok(match code {
+            NO_OP => ActionCode::NoOp,
+            TRANS => ActionCode::Trans,
+            0x0004..=0x00FF => match keycode(code as u8) {
+                Some(kc) => ActionCode::KeyCode(kc),
+                None => return Err(DecodeError::UnknownEncoding(code)),
+            },
+            TO_LAYER..=0x521F => ActionCode::ToLayer(layer(TO_LAYER)),
+            LAYER..=0x523F => ActionCode::Layer(layer(LAYER)),
+            DEFAULT_LAYER..=0x525F => ActionCode::DefaultLayer(layer(DEFAULT_LAYER)),
+            TOGGLE_LAYER..=0x527F => ActionCode::ToggleLayer(layer(TOGGLE_LAYER)),
+            DYNAMIC_MACRO_RECORD..=0x7C54 => {
+                ActionCode::DynamicMacroRecord(layer(DYNAMIC_MACRO_RECORD))
+            }
+            DYNAMIC_MACRO_PLAY..=0x7C56 => ActionCode::DynamicMacroPlay(layer(DYNAMIC_MACRO_PLAY)),
+            DYNAMIC_MACRO_STOP => ActionCode::DynamicMacroStop,
+            REPEAT => ActionCode::Repeat,
+            LAYER_LOCK => ActionCode::LayerLock,
+            USER..=USER_LAST => ActionCode::User(code - USER),
+            _ => return Err(DecodeError::UnknownEncoding(code)),
+        })
+    }
+
+    /// Encodes the action in 16 bits.
+    ///
+    /// Returns `None` if the action is out of the encodable range,
+    /// as a layer greater than [`MAX_LAYERS`].
+    pub fn encode(self) -> Option<u16> {
+        let layer = |base: u16, layer: u8| (layer < MAX_LAYERS).then(|| base + layer as u16);
+        let dynamic_macro =
+            |base: u16, slot: u8| (slot < DYNAMIC_MACROS).then(|| base + slot as u16);
+        match self {
+            ActionCode::NoOp => Some(NO_OP),
+            ActionCode::Trans => Some(TRANS),
+            // As `KC_NO` in QMK, the no key code is the no operation.
+            ActionCode::KeyCode(KeyCode::No) => Some(NO_OP),
+            ActionCode::KeyCode(kc) => (kc > KeyCode::ErrorUndefined).then_some(kc as u16),
+            ActionCode::Layer(l) => layer(LAYER, l),
+            ActionCode::DefaultLayer(l) => layer(DEFAULT_LAYER, l),
+            ActionCode::ToggleLayer(l) => layer(TOGGLE_LAYER, l),
+            ActionCode::ToLayer(l) => layer(TO_LAYER, l),
+            ActionCode::LayerLock => Some(LAYER_LOCK),
+            ActionCode::Repeat => Some(REPEAT),
+            ActionCode::DynamicMacroRecord(s) => dynamic_macro(DYNAMIC_MACRO_RECORD, s),
+            ActionCode::DynamicMacroStop => Some(DYNAMIC_MACRO_STOP),
+            ActionCode::DynamicMacroPlay(s) => dynamic_macro(DYNAMIC_MACRO_PLAY, s),
+            ActionCode::User(i) => (i < MAX_USER_ACTIONS).then(|| USER + i),
+        }
+    }
+
+    /// Gets the code of an action.
+    ///
+    /// The actions that have no dedicated code are searched in the
+    /// table of user actions. Returns `None` if the action can't be
+    /// encoded.
+    pub fn from_action<T: PartialEq>(
+        action: &Action<T>,
+        user_actions: &[Action<T>],
+    ) -> Option<Self> {
+        let code = match *action {
+            Action::NoOp => Some(ActionCode::NoOp),
+            Action::Trans => Some(ActionCode::Trans),
+            Action::KeyCode(kc) => Some(ActionCode::KeyCode(kc)),
+            Action::Layer(l) => u8::try_from(l).ok().map(ActionCode::Layer),
+            Action::DefaultLayer(l) => u8::try_from(l).ok().map(ActionCode::DefaultLayer),
+            Action::ToggleLayer(l) => u8::try_from(l).ok().map(ActionCode::ToggleLayer),
+            Action::ToLayer(l) => u8::try_from(l).ok().map(ActionCode::ToLayer),
+            Action::LayerLock => Some(ActionCode::LayerLock),
+            Action::Repeat => Some(ActionCode::Repeat),
+            Action::DynamicMacroRecord(s) => {
+                u8::try_from(s).ok().map(ActionCode::DynamicMacroRecord)
+            }
+            Action::DynamicMacroStop => Some(ActionCode::DynamicMacroStop),
+            Action::DynamicMacroPlay(s) => u8::try_from(s).ok().map(ActionCode::DynamicMacroPlay),
+            _ => None,
+        };
+        code.filter(|c| c.encode().is_some()).or_else(|| {
+            let i = user_actions.iter().position(|a| a == action)?;
+            let code = ActionCode::User(u16::try_from(i).ok()?);
+            code.encode().map(|_| code)
+        })
+    }
+
+    /// Gets the action corresponding to this code.
+    ///
+    /// The user actions are taken from the given table, returning
+    /// [`DecodeError::UnknownUserAction`] if the index is out of the
+    /// table.
+    pub fn to_action<T>(
+        self,
+        user_actions: &'static [Action<T>],
+    ) -> Result<&'static Action<T>, DecodeError> {
+        let code = self.encode().ok_or(DecodeError::OutOfRange)?;
+        Ok(match self {
+            ActionCode::NoOp => &Action::NoOp,
+            ActionCode::Trans => &Action::Trans,
+            ActionCode::KeyCode(kc) => keycode_action(kc),
+            ActionCode::Layer(l) => layers!(Layer, l),
+            ActionCode::DefaultLayer(l) => layers!(DefaultLayer, l),
+            ActionCode::ToggleLayer(l) => layers!(ToggleLayer, l),
+            ActionCode::ToLayer(l) => layers!(ToLayer, l),
+            ActionCode::LayerLock => &Action::LayerLock,
+            ActionCode::Repeat => &Action::Repeat,
+            ActionCode::DynamicMacroRecord(0) => &Action::DynamicMacroRecord(0),
+            ActionCode::DynamicMacroRecord(_) => &Action::DynamicMacroRecord(1),
+            ActionCode::DynamicMacroStop => &Action::DynamicMacroStop,
+            ActionCode::DynamicMacroPlay(0) => &Action::DynamicMacroPlay(0),
+            ActionCode::DynamicMacroPlay(_) => &Action::DynamicMacroPlay(1),
+            ActionCode::User(i) => user_actions
+                .get(i as usize)
+                .ok_or(DecodeError::UnknownUserAction(code))?,
+        })
+    }
+}
+
+/// Returns the length of a serialized keymap of `L` layers, `R` rows
+/// and `C` columns.
+pub const fn serialized_len<const C: usize, const R: usize, const L: usize>() -> usize {
+    HEADER_LEN + 2 * L * R * C
+}
+
+/// Serializes a keymap into `buf`, returning the length of the
+/// serialized keymap.
+///
+/// The actions that have no dedicated code must be in the table of
+/// user actions.
+pub fn save_keymap<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static>(
+    keymap: &RuntimeKeymap<C, R, L, T>,
+    user_actions: &[Action<T>],
+    buf: &mut [u8],
+) -> Result<usize, EncodeError> {
+    let len = serialized_len::<C, R, L>();
+    let header = [L, R, C].map(u8::try_from);
+    let [Ok(l), Ok(r), Ok(c)] = header else {
+        return Err(EncodeError::KeymapTooLarge);
+    };
+    let buf = buf.get_mut(..len).ok_or(EncodeError::BufferTooSmall)?;
+    let (header, data) = buf.split_at_mut(HEADER_LEN);
+    header.copy_from_slice(&[VERSION, l, r, c]);
+    let mut chunks = data.chunks_exact_mut(2);
+    for layer in 0..L {
+        for row in 0..R {
+            for col in 0..C {
+                let unsupported = EncodeError::UnsupportedAction { layer, row, col };
+                let action = keymap.action(layer, row, col).ok_or(unsupported)?;
+                let code = ActionCode::from_action(action, user_actions)
+                    .and_then(ActionCode::encode)
+                    .ok_or(unsupported)?;
+                if let Some(chunk) = chunks.next() {
+                    chunk.copy_from_slice(&code.to_be_bytes());
+                }
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Loads a serialized keymap into a runtime keymap.
+///
+/// The keymap is validated before being loaded: on error, the
+/// keymap is not modified.
+pub fn load_keymap<const C: usize, const R: usize, const L: usize, T: 'static>(
+    keymap: &mut RuntimeKeymap<C, R, L, T>,
+    user_actions: &'static [Action<T>],
+    data: &[u8],
+) -> Result<(), DecodeError> {
+    if data.len() < HEADER_LEN {
+        return Err(DecodeError::InvalidLength);
+    }
+    let (header, data) = data.split_at(HEADER_LEN);
+    if header[0] != VERSION {
+        return Err(DecodeError::UnsupportedVersion(header[0]));
+    }
+    if header[1..] != [L, R, C].map(|d| d as u8) || [L, R, C].iter().any(|&d| d > 255) {
+        return Err(DecodeError::InvalidDimensions);
+    }
+    if data.len() != serialized_len::<C, R, L>() - HEADER_LEN {
+        return Err(DecodeError::InvalidLength);
+    }
+    let action = |i: usize| {
+        let code = u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+        ActionCode::decode(code)?.to_action(user_actions)
+    };
+    for i in 0..L * R * C {
+        action(i)?;
+    }
+    for layer in 0..L {
+        for row in 0..R {
+            for col in 0..C {
+                let action = action((layer * R + row) * C + col)?;
+                keymap.set_action(layer, row, col, action);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::{k, l, HoldTapAction, HoldTapConfig};
+    use crate::key_code::KeyCode::*;
+    use crate::layout::Layers;
+    use core::convert::Infallible;
+
+    static USER_ACTIONS: [Action; 1] = [Action::HoldTap(&HoldTapAction {
+        timeout: 200,
+        hold: k(LCtrl),
+        tap: k(Escape),
+        config: HoldTapConfig::Default,
+        tap_hold_interval: 0,
+        require_prior_idle: 0,
+        retro_tap: false,
+    })];
+
+    #[test]
+    fn encode_decode() {
+        for code in 0..=u16::MAX {
+            let decoded = ActionCode::decode(code);
+            if let Ok(action_code) = decoded {
+                assert_eq!(action_code.encode(), Some(code));
+            }
+        }
+        assert_eq!(ActionCode::decode(0x0004), Ok(ActionCode::KeyCode(A)));
+        assert_eq!(ActionCode::decode(0x00E1), Ok(ActionCode::KeyCode(LShift)));
+        assert_eq!(ActionCode::decode(0x5222), Ok(ActionCode::Layer(2)));
+        assert_eq!(ActionCode::decode(0x7E40), Ok(ActionCode::User(0)));
+        assert_eq!(
+            ActionCode::decode(0x0002),
+            Err(DecodeError::UnknownEncoding(0x0002))
+        );
+        assert_eq!(
+            ActionCode::decode(0x00A5),
+            Err(DecodeError::UnknownEncoding(0x00A5))
+        );
+        assert_eq!(
+            ActionCode::decode(0x1234),
+            Err(DecodeError::UnknownEncoding(0x1234))
+        );
+        assert_eq!(ActionCode::Layer(32).encode(), None);
+        assert_eq!(ActionCode::KeyCode(No).encode(), Some(0x0000));
+        assert_eq!(ActionCode::KeyCode(ErrorRollOver).encode(), None);
+    }
+
+    #[test]
+    fn actions() {
+        let user: &'static [Action] = &USER_ACTIONS;
+        for code in [
+            0x0000, 0x0001, 0x0004, 0x00FB, 0x521F, 0x5240, 0x7C54, 0x7C79, 0x7E40,
+        ] {
+            let action = ActionCode::decode(code).unwrap().to_action(user).unwrap();
+            let action_code = ActionCode::from_action(action, user).unwrap();
+            assert_eq!(action_code.encode(), Some(code));
+        }
+        assert_eq!(
+            ActionCode::decode(0x5223).unwrap().to_action(user),
+            Ok(&l::<Infallible>(3))
+        );
+        assert_eq!(
+            ActionCode::User(1).to_action(user),
+            Err(DecodeError::UnknownUserAction(0x7E41))
+        );
+        assert_eq!(
+            ActionCode::ToLayer(40).to_action(user),
+            Err(DecodeError::OutOfRange)
+        );
+        assert_eq!(
+            ActionCode::from_action(&k(No), user).and_then(ActionCode::encode),
+            Some(0x0000)
+        );
+        assert_eq!(ActionCode::from_action(&Action::Layer(32), user), None);
+        assert_eq!(
+            ActionCode::from_action(&Action::MultipleKeyCodes(&(&[LShift, A] as &[_])), user),
+            None
+        );
+    }
+
+    #[test]
+    fn save_load() {
+        static LAYERS: Layers<2, 1, 3> = [
+            [[k(A), Action::Layer(1)]],
+            [[Action::Trans, USER_ACTIONS[0]]],
+            [[k(No), Action::NoOp]],
+        ];
+        static EMPTY: Layers<2, 1, 3> = [[[k(A); 2]]; 3];
+        let mut buf = [0; 16];
+        let keymap = RuntimeKeymap::new(&LAYERS);
+        let len = serialized_len::<2, 1, 3>();
+        assert_eq!(save_keymap(&keymap, &USER_ACTIONS, &mut buf), Ok(len));
+        assert_eq!(
+            &buf[..len],
+            &[1, 3, 1, 2, 0x00, 0x04, 0x52, 0x21, 0x00, 0x01, 0x7E, 0x40, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            save_keymap(&keymap, &[], &mut buf),
+            Err(EncodeError::UnsupportedAction {
+                layer: 1,
+                row: 0,
+                col: 1
+            })
+        );
+        assert_eq!(
+            save_keymap(&keymap, &USER_ACTIONS, &mut buf[..len - 1]),
+            Err(EncodeError::BufferTooSmall)
+        );
+
+        let mut loaded = RuntimeKeymap::new(&EMPTY);
+        assert_eq!(load_keymap(&mut loaded, &USER_ACTIONS, &buf[..len]), Ok(()));
+        for (layer, row, col) in [(0, 0, 0), (0, 0, 1), (1, 0, 0), (1, 0, 1)] {
+            assert_eq!(
+                loaded.action(layer, row, col),
+                keymap.action(layer, row, col)
+            );
+        }
+        // the no key code is loaded as the no operation
+        assert_eq!(loaded.action(2, 0, 0), Some(&Action::NoOp));
+    }
+
+    #[test]
+    fn load_errors() {
+        static EMPTY: Layers<2, 1, 1> = [[[Action::NoOp; 2]]];
+        let mut keymap = RuntimeKeymap::new(&EMPTY);
+        let mut load = |data: &[u8]| load_keymap(&mut keymap, &USER_ACTIONS, data);
+        assert_eq!(load(&[1, 1, 1]), Err(DecodeError::InvalidLength));
+        assert_eq!(
+            load(&[2, 1, 1, 2, 0, 4, 0, 5]),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            load(&[1, 2, 1, 2, 0, 4, 0, 5]),
+            Err(DecodeError::InvalidDimensions)
+        );
+        assert_eq!(
+            load(&[1, 1, 1, 2, 0, 4, 0]),
+            Err(DecodeError::InvalidLength)
+        );
+        assert_eq!(
+            load(&[1, 1, 1, 2, 0, 4, 0x12, 0x34]),
+            Err(DecodeError::UnknownEncoding(0x1234))
+        );
+        assert_eq!(
+            load(&[1, 1, 1, 2, 0, 4, 0x7E, 0x41]),
+            Err(DecodeError::UnknownUserAction(0x7E41))
+        );
+        // The keymap is not modified on error
+        assert_eq!(keymap.action(0, 0, 0), Some(&Action::NoOp));
+    }
+
+    #[test]
+    fn set_code() {
+        static EMPTY: Layers<2, 1, 1> = [[[Action::NoOp; 2]]];
+        let mut keymap = RuntimeKeymap::new(&EMPTY);
+        assert_eq!(keymap.set_code(0, 0, 0, 0x0004, &USER_ACTIONS), Ok(()));
+        assert_eq!(keymap.action(0, 0, 0), Some(&k(A)));
+        assert_eq!(keymap.set_code(0, 0, 1, 0x7E40, &USER_ACTIONS), Ok(()));
+        assert_eq!(keymap.action(0, 0, 1), Some(&USER_ACTIONS[0]));
+        assert_eq!(
+            keymap.set_code(0, 0, 0, 0x1234, &USER_ACTIONS),
+            Err(DecodeError::UnknownEncoding(0x1234))
+        );
+        assert_eq!(
+            keymap.set_code(0, 0, 0, 0x7E41, &USER_ACTIONS),
+            Err(DecodeError::UnknownUserAction(0x7E41))
+        );
+        assert_eq!(
+            keymap.set_code(1, 0, 0, 0x0005, &USER_ACTIONS),
+            Err(DecodeError::InvalidKey)
+        );
+        assert_eq!(
+            keymap.set_code(0, 0, 2, 0x0005, &USER_ACTIONS),
+            Err(DecodeError::InvalidKey)
+        );
+        // The keymap is not modified on error
+        assert_eq!(keymap.action(0, 0, 0), Some(&k(A)));
+    }
+}
@@ -13,6 +13,8 @@ use usb_device::UsbError;
 
 const SPECIFICATION_RELEASE: u16 = 0x111;
 const INTERFACE_CLASS_HID: u8 = 0x03;
+/// The maximum size of an interrupt packet at full speed.
+const MAX_PACKET_LEN: usize = 64;
 
 pub struct Error;
 
@@ -31,6 +33,14 @@ pub enum Protocol {
     Mouse = 0x02,
 }
 
+/// The protocol selected by the host with SET_PROTOCOL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ProtocolMode {
+    Boot = 0x00,
+    Report = 0x01,
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum DescriptorType {
@@ -100,6 +110,25 @@ pub trait HidDevice {
     ) -> Result<(), Error>;
 
     fn get_report(&mut self, report_type: ReportType, report_id: u8) -> Result<&[u8], Error>;
+
+    /// Called when the host selects the protocol, and on USB reset
+    /// with `ProtocolMode::Report`, the default protocol.
+    fn set_protocol_mode(&mut self, _mode: ProtocolMode) {}
+
+    /// Called when the host sets the idle rate, in units of 4 ms, 0
+    /// meaning that the report is sent only when it changes. The
+    /// idle rate is reset to 0 on USB reset.
+    fn set_idle(&mut self, _report_id: u8, _duration: u8) {}
+
+    /// Returns the input report to write on the interrupt endpoint
+    /// as a response to the host, if any. It is written on poll and
+    /// tick, until [`HidDevice::input_report_sent`] is called.
+    fn pending_input_report(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Called when the pending input report has been written.
+    fn input_report_sent(&mut self) {}
 }
 
 pub struct HidClass<'a, B: UsbBus, D: HidDevice> {
@@ -107,6 +136,9 @@ pub struct HidClass<'a, B: UsbBus, D: HidDevice> {
     interface: InterfaceNumber,
     endpoint_interrupt_in: EndpointIn<'a, B>,
     expect_interrupt_in_complete: bool,
+    protocol_mode: ProtocolMode,
+    idle_rate: u8,
+    idle_elapsed: u32,
 }
 
 impl<B: UsbBus, D: HidDevice> HidClass<'_, B, D> {
@@ -117,6 +149,9 @@ impl<B: UsbBus, D: HidDevice> HidClass<'_, B, D> {
             interface: alloc.interface(),
             endpoint_interrupt_in: alloc.interrupt(max_packet_size, 10),
             expect_interrupt_in_complete: false,
+            protocol_mode: ProtocolMode::Report,
+            idle_rate: 0,
+            idle_elapsed: 0,
         }
     }
 
@@ -125,16 +160,67 @@ impl<B: UsbBus, D: HidDevice> HidClass<'_, B, D> {
     }
 
     pub fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
-        if self.expect_interrupt_in_complete {
-            return Ok(0);
+        self.write_interrupt_in(data)
+    }
+
+    /// Returns the protocol selected by the host.
+    pub fn protocol_mode(&self) -> ProtocolMode {
+        self.protocol_mode
+    }
+
+    /// Returns the idle rate set by the host, in units of 4 ms.
+    pub fn idle_rate(&self) -> u8 {
+        self.idle_rate
+    }
+
+    /// Must be called every millisecond. Writes the pending input
+    /// report of the device (see
+    /// [`HidDevice::pending_input_report`]). When the idle rate is not
+    /// 0 and no report was written during the idle period, the
+    /// current input report of the device is written again.
+    pub fn tick(&mut self) -> Result<(), Error> {
+        self.write_pending_report()?;
+        if self.idle_rate == 0 {
+            return Ok(());
+        }
+        self.idle_elapsed = self.idle_elapsed.saturating_add(1);
+        if self.idle_elapsed < self.idle_rate as u32 * 4 || self.expect_interrupt_in_complete {
+            return Ok(());
         }
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = copy_report(self.device.get_report(ReportType::Input, 0)?, &mut buf);
+        self.write_interrupt_in(&buf[..len]).map(|_| ())
+    }
 
-        if data.len() >= 8 {
-            self.expect_interrupt_in_complete = true;
+    fn write_pending_report(&mut self) -> Result<(), Error> {
+        if self.expect_interrupt_in_complete {
+            return Ok(());
+        }
+        let mut buf = [0; MAX_PACKET_LEN];
+        let len = match self.device.pending_input_report() {
+            Some(data) => copy_report(data, &mut buf),
+            None => return Ok(()),
+        };
+        if self.write_interrupt_in(&buf[..len])? != 0 {
+            self.device.input_report_sent();
         }
+        Ok(())
+    }
 
+    /// Writes a report on the interrupt endpoint, returning the
+    /// number of bytes written, 0 if the endpoint is busy.
+    fn write_interrupt_in(&mut self, data: &[u8]) -> Result<usize, Error> {
+        if self.expect_interrupt_in_complete {
+            return Ok(0);
+        }
         match self.endpoint_interrupt_in.write(data) {
-            Ok(count) => Ok(count),
+            Ok(count) => {
+                if data.len() >= 8 {
+                    self.expect_interrupt_in_complete = true;
+                }
+                self.idle_elapsed = 0;
+                Ok(count)
+            }
             Err(UsbError::WouldBlock) => Ok(0),
             Err(_) => Err(Error),
         }
@@ -166,11 +252,25 @@ impl<B: UsbBus, D: HidDevice> HidClass<'_, B, D> {
     }
 }
 
+/// Copies a report in `buf`, returning its length.
+fn copy_report(data: &[u8], buf: &mut [u8; MAX_PACKET_LEN]) -> usize {
+    let len = data.len().min(MAX_PACKET_LEN);
+    buf[..len].copy_from_slice(&data[..len]);
+    len
+}
+
 impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
-    fn poll(&mut self) {}
+    fn poll(&mut self) {
+        self.write_pending_report().ok();
+    }
 
     fn reset(&mut self) {
         self.expect_interrupt_in_complete = false;
+        self.protocol_mode = ProtocolMode::Report;
+        self.device.set_protocol_mode(ProtocolMode::Report);
+        self.idle_rate = 0;
+        self.idle_elapsed = 0;
+        self.device.set_idle(0, 0);
     }
 
     fn get_configuration_descriptors(
@@ -186,7 +286,7 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
 
         let report_descriptor = self.device.report_descriptor();
         let descriptor_len = report_descriptor.len();
-        if descriptor_len > u16::max_value() as usize {
+        if descriptor_len > u16::MAX as usize {
             return Err(UsbError::InvalidState);
         }
         let descriptor_len = (descriptor_len as u16).to_le_bytes();
@@ -224,23 +324,31 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
     fn control_in(&mut self, xfer: ControlIn<B>) {
         let req = xfer.request();
         match (req.request_type, req.recipient) {
-            (RequestType::Standard, Recipient::Interface) => {
-                if req.request == control::Request::GET_DESCRIPTOR {
-                    let (dtype, index) = req.descriptor_type_index();
-                    if dtype == DescriptorType::Report as u8
-                        && index == 0
-                        && req.index == self.interface_index()
-                    {
-                        let descriptor = self.device.report_descriptor();
-                        xfer.accept_with(descriptor).ok();
-                    }
+            (RequestType::Standard, Recipient::Interface)
+                if req.request == control::Request::GET_DESCRIPTOR =>
+            {
+                let (dtype, index) = req.descriptor_type_index();
+                if dtype == DescriptorType::Report as u8
+                    && index == 0
+                    && req.index == self.interface_index()
+                {
+                    let descriptor = self.device.report_descriptor();
+                    xfer.accept_with(descriptor).ok();
                 }
             }
             (RequestType::Class, Recipient::Interface) => {
-                if let Some(request) = Request::new(req.request) {
-                    if request == Request::GetReport && req.index == self.interface_index() {
-                        self.get_report(xfer);
+                if req.index != self.interface_index() {
+                    return;
+                }
+                match Request::new(req.request) {
+                    Some(Request::GetReport) => self.get_report(xfer),
+                    Some(Request::GetIdle) => {
+                        xfer.accept_with(&[self.idle_rate]).ok();
+                    }
+                    Some(Request::GetProtocol) => {
+                        xfer.accept_with(&[self.protocol_mode as u8]).ok();
                     }
+                    _ => {}
                 }
             }
             _ => {}
@@ -251,10 +359,314 @@ impl<B: UsbBus, D: HidDevice> UsbClass<B> for HidClass<'_, B, D> {
         let req = xfer.request();
         if req.request_type == RequestType::Class && req.recipient == Recipient::Interface {
             if let Some(request) = Request::new(req.request) {
-                if request == Request::SetReport && req.index == self.interface_index() {
-                    self.set_report(xfer);
+                if req.index != self.interface_index() {
+                    return;
+                }
+                match request {
+                    Request::SetReport => self.set_report(xfer),
+                    Request::SetProtocol => {
+                        let mode = match req.value {
+                            0 => ProtocolMode::Boot,
+                            1 => ProtocolMode::Report,
+                            _ => {
+                                xfer.reject().ok();
+                                return;
+                            }
+                        };
+                        self.protocol_mode = mode;
+                        self.device.set_protocol_mode(mode);
+                        xfer.accept().ok();
+                    }
+                    Request::SetIdle => {
+                        let [duration, report_id] = req.value.to_be_bytes();
+                        // Only one idle rate is handled, for all the reports
+                        if report_id == 0 {
+                            self.idle_rate = duration;
+                            self.idle_elapsed = 0;
+                        }
+                        self.device.set_idle(report_id, duration);
+                        xfer.accept().ok();
+                    }
+                    _ => {}
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+    use usb_device::bus::PollResult;
+    use usb_device::endpoint::EndpointType;
+    use usb_device::prelude::*;
+    use usb_device::UsbDirection;
+
+    /// A USB bus recording the packets written on the endpoints, and
+    /// receiving the SETUP packets of the control requests. When
+    /// `busy`, the class endpoints can't be written.
+    #[derive(Default)]
+    struct MockBus {
+        next_index: usize,
+        written: Mutex<Vec<(usize, Vec<u8>)>>,
+        setup: Mutex<Option<[u8; 8]>>,
+        busy: Mutex<bool>,
+    }
+
+    impl MockBus {
+        /// Returns the packets written on the endpoint of the given
+        /// index since the last call.
+        fn take_written(&self, index: usize) -> Vec<Vec<u8>> {
+            let mut written = self.written.lock().unwrap();
+            let (taken, kept) = written.drain(..).partition(|(i, _)| *i == index);
+            *written = kept;
+            taken.into_iter().map(|(_, data)| data).collect()
+        }
+    }
+
+    /// Sends a class request without data stage to the interface 0.
+    fn request(
+        usb_dev: &mut UsbDevice<MockBus>,
+        class: &mut dyn UsbClass<MockBus>,
+        req: Request,
+        value: u16,
+    ) {
+        let [value_lo, value_hi] = value.to_le_bytes();
+        let setup = [0x21, req as u8, value_lo, value_hi, 0, 0, 0, 0];
+        *usb_dev.bus().setup.lock().unwrap() = Some(setup);
+        assert!(usb_dev.poll(&mut [class]));
+    }
+
+    impl UsbBus for MockBus {
+        fn alloc_ep(
+            &mut self,
+            ep_dir: UsbDirection,
+            ep_addr: Option<EndpointAddress>,
+            _ep_type: EndpointType,
+            _max_packet_size: u16,
+            _interval: u8,
+        ) -> usb_device::Result<EndpointAddress> {
+            Ok(ep_addr.unwrap_or_else(|| {
+                self.next_index += 1;
+                EndpointAddress::from_parts(self.next_index, ep_dir)
+            }))
+        }
+        fn enable(&mut self) {}
+        fn reset(&self) {}
+        fn set_device_address(&self, _addr: u8) {}
+        fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+            if ep_addr.index() != 0 && *self.busy.lock().unwrap() {
+                return Err(UsbError::WouldBlock);
+            }
+            let written = (ep_addr.index(), buf.to_vec());
+            self.written.lock().unwrap().push(written);
+            Ok(buf.len())
+        }
+        fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> usb_device::Result<usize> {
+            match self.setup.lock().unwrap().take() {
+                Some(setup) if ep_addr.index() == 0 => {
+                    buf[..8].copy_from_slice(&setup);
+                    Ok(8)
+                }
+                _ => Err(UsbError::WouldBlock),
+            }
+        }
+        fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) {}
+        fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool {
+            false
+        }
+        fn suspend(&self) {}
+        fn resume(&self) {}
+        fn poll(&self) -> PollResult {
+            match *self.setup.lock().unwrap() {
+                Some(_) => PollResult::Data {
+                    ep_out: 0,
+                    ep_in_complete: 0,
+                    ep_setup: 1,
+                },
+                None => PollResult::None,
+            }
+        }
+    }
+
+    /// A HID device recording the notifications of the class.
+    #[derive(Default)]
+    struct MockDevice {
+        report: [u8; 8],
+        protocol_mode: Option<ProtocolMode>,
+        idle: Option<(u8, u8)>,
+    }
+
+    impl HidDevice for MockDevice {
+        fn subclass(&self) -> Subclass {
+            Subclass::BootInterface
+        }
+        fn protocol(&self) -> Protocol {
+            Protocol::Keyboard
+        }
+        fn max_packet_size(&self) -> u16 {
+            8
+        }
+        fn report_descriptor(&self) -> &[u8] {
+            &[]
+        }
+        fn set_report(&mut self, _: ReportType, _: u8, _: &[u8]) -> Result<(), Error> {
+            Err(Error)
+        }
+        fn get_report(&mut self, _: ReportType, _: u8) -> Result<&[u8], Error> {
+            Ok(&self.report)
+        }
+        fn set_protocol_mode(&mut self, mode: ProtocolMode) {
+            self.protocol_mode = Some(mode);
+        }
+        fn set_idle(&mut self, report_id: u8, duration: u8) {
+            self.idle = Some((report_id, duration));
+        }
+    }
+
+    #[test]
+    fn idle_rate() {
+        let alloc = UsbBusAllocator::new(MockBus::default());
+        let mut class = HidClass::new(MockDevice::default(), &alloc);
+        let mut usb_dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x27db)).build();
+        let ep = class.endpoint_interrupt_in.address();
+
+        // no resend by default
+        for _ in 0..100 {
+            assert!(class.tick().is_ok());
+        }
+        assert!(usb_dev.bus().take_written(1).is_empty());
+
+        // 8 ms
+        request(&mut usb_dev, &mut class, Request::SetIdle, 0x0200);
+        assert_eq!(class.idle_rate(), 2);
+        assert_eq!(class.device_mut().idle, Some((0, 2)));
+        class.device_mut().report = [0, 0, 4, 0, 0, 0, 0, 0];
+        for _ in 0..7 {
+            assert!(class.tick().is_ok());
+        }
+        assert!(usb_dev.bus().take_written(1).is_empty());
+        assert!(class.tick().is_ok());
+        assert_eq!(usb_dev.bus().take_written(1), [[0, 0, 4, 0, 0, 0, 0, 0]]);
+
+        // not resent before the previous report is sent
+        for _ in 0..8 {
+            assert!(class.tick().is_ok());
+        }
+        assert!(usb_dev.bus().take_written(1).is_empty());
+        class.endpoint_in_complete(ep);
+        assert!(class.tick().is_ok());
+        assert_eq!(usb_dev.bus().take_written(1).len(), 1);
+        class.endpoint_in_complete(ep);
+
+        // a written report restarts the idle period
+        for _ in 0..4 {
+            assert!(class.tick().is_ok());
+        }
+        assert!(class.write(&[0; 8]).is_ok());
+        assert_eq!(usb_dev.bus().take_written(1).len(), 1);
+        class.endpoint_in_complete(ep);
+        for _ in 0..7 {
+            assert!(class.tick().is_ok());
+        }
+        assert!(usb_dev.bus().take_written(1).is_empty());
+        assert!(class.tick().is_ok());
+        assert_eq!(usb_dev.bus().take_written(1).len(), 1);
+
+        // resent as soon as the endpoint is not busy
+        class.endpoint_in_complete(ep);
+        *usb_dev.bus().busy.lock().unwrap() = true;
+        for _ in 0..10 {
+            assert!(class.tick().is_ok());
+        }
+        assert!(usb_dev.bus().take_written(1).is_empty());
+        *usb_dev.bus().busy.lock().unwrap() = false;
+        assert!(class.tick().is_ok());
+        assert_eq!(usb_dev.bus().take_written(1).len(), 1);
+
+        // the idle rate of another report is ignored
+        request(&mut usb_dev, &mut class, Request::SetIdle, 0x0001);
+        assert_eq!(class.idle_rate(), 2);
+        assert_eq!(class.device_mut().idle, Some((1, 0)));
+    }
+
+    #[test]
+    fn reset_protocol_mode() {
+        let alloc = UsbBusAllocator::new(MockBus::default());
+        let mut class = HidClass::new(MockDevice::default(), &alloc);
+        let mut usb_dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x27db)).build();
+        assert_eq!(class.protocol_mode(), ProtocolMode::Report);
+
+        request(&mut usb_dev, &mut class, Request::SetProtocol, 0);
+        assert_eq!(class.protocol_mode(), ProtocolMode::Boot);
+        assert_eq!(class.device_mut().protocol_mode, Some(ProtocolMode::Boot));
+        request(&mut usb_dev, &mut class, Request::SetIdle, 0x0100);
+        assert_eq!(class.idle_rate(), 1);
+
+        class.reset();
+        assert_eq!(class.protocol_mode(), ProtocolMode::Report);
+        assert_eq!(class.device_mut().protocol_mode, Some(ProtocolMode::Report));
+        assert_eq!(class.idle_rate(), 0);
+        assert_eq!(class.device_mut().idle, Some((0, 0)));
+    }
+
+    #[test]
+    fn via_response() {
+        use crate::action::{k, Action};
+        use crate::key_code::KeyCode::*;
+        use crate::keymap::{Keymap, RuntimeKeymap};
+        use crate::layout::Layers;
+        use crate::via::Via;
+
+        static LAYERS: Layers<2, 1, 1> = [[[k(A), k(B)]]];
+        static USER_ACTIONS: [Action; 0] = [];
+        let alloc = UsbBusAllocator::new(MockBus::default());
+        let mut class = HidClass::new(Via::new(&LAYERS, &USER_ACTIONS), &alloc);
+        let usb_dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x16c0, 0x27db)).build();
+        let mut keymap = RuntimeKeymap::new(&LAYERS);
+
+        // set the keycode of (0, 0, 1) to C
+        let command = [0x05, 0, 0, 1, 0x00, 0x06];
+        assert!(class
+            .device_mut()
+            .set_report(ReportType::Output, 0, &command)
+            .is_ok());
+        class.poll();
+        assert!(usb_dev.bus().take_written(1).is_empty());
+        assert!(class.device_mut().process(&mut keymap));
+        assert_eq!(keymap.action(0, 0, 1), Some(&k(C)));
+
+        // the response is kept while the endpoint is busy
+        *usb_dev.bus().busy.lock().unwrap() = true;
+        class.poll();
+        assert!(class.tick().is_ok());
+        *usb_dev.bus().busy.lock().unwrap() = false;
+        assert!(usb_dev.bus().take_written(1).is_empty());
+
+        // the response is written once
+        class.poll();
+        let mut response = [0; 32];
+        response[..6].copy_from_slice(&command);
+        assert_eq!(usb_dev.bus().take_written(1), [response.to_vec()]);
+        class.endpoint_in_complete(class.endpoint_interrupt_in.address());
+        class.poll();
+        assert!(class.tick().is_ok());
+        assert!(usb_dev.bus().take_written(1).is_empty());
+
+        // written on tick, when the previous one has been sent
+        let command = [0x11];
+        assert!(class
+            .device_mut()
+            .set_report(ReportType::Output, 0, &command)
+            .is_ok());
+        assert!(!class.device_mut().process(&mut keymap));
+        let mut response = [0; 32];
+        response[..2].copy_from_slice(&[0x11, 1]);
+        assert!(class.tick().is_ok());
+        assert_eq!(usb_dev.bus().take_written(1), [response.to_vec()]);
+    }
+}
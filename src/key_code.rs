@@ -321,3 +321,65 @@ impl KbHidReport {
         }
     }
 }
+
+/// The length of a [`NkroKbHidReport`].
+pub const NKRO_REPORT_LEN: usize = 33;
+
+/// An N-key rollover keyboard USB HID report.
+///
+/// It can handle any modifier and any number of keys: the first
+/// byte is the modifiers, followed by a bitmap of the 256 key
+/// codes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NkroKbHidReport([u8; NKRO_REPORT_LEN]);
+
+impl Default for NkroKbHidReport {
+    fn default() -> Self {
+        Self([0; NKRO_REPORT_LEN])
+    }
+}
+
+impl core::iter::FromIterator<KeyCode> for NkroKbHidReport {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = KeyCode>,
+    {
+        let mut res = Self::default();
+        for kc in iter {
+            res.pressed(kc);
+        }
+        res
+    }
+}
+
+impl From<&KbHidReport> for NkroKbHidReport {
+    /// Converts a boot report, which error key codes are ignored.
+    fn from(report: &KbHidReport) -> Self {
+        let mut res = Self::default();
+        res.0[0] = report.0[0];
+        for &c in &report.0[2..] {
+            if c > KeyCode::ErrorUndefined as u8 {
+                res.0[1 + c as usize / 8] |= 1 << (c % 8);
+            }
+        }
+        res
+    }
+}
+
+impl NkroKbHidReport {
+    /// Returns the byte slice corresponding to the report.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Add the given key code to the report. The error key codes
+    /// are ignored, as the report can't be full.
+    pub fn pressed(&mut self, kc: KeyCode) {
+        use KeyCode::*;
+        match kc {
+            No | ErrorRollOver | PostFail | ErrorUndefined => (),
+            kc if kc.is_modifier() => self.0[0] |= kc.as_modifier_bit(),
+            _ => self.0[1 + kc as usize / 8] |= 1 << (kc as u8 % 8),
+        }
+    }
+}
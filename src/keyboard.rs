@@ -1,7 +1,7 @@
 //! Keyboard HID device implementation.
 
-use crate::hid::{self, HidDevice, Protocol, ReportType, Subclass};
-use crate::key_code::KbHidReport;
+use crate::hid::{self, HidDevice, Protocol, ProtocolMode, ReportType, Subclass};
+use crate::key_code::{KbHidReport, KeyCode, NkroKbHidReport};
 
 /// A trait to manage keyboard LEDs.
 ///
@@ -56,9 +56,49 @@ const REPORT_DESCRIPTOR: &[u8] = &[
     0xC0,              // End Collection
 ];
 
+#[rustfmt::skip]
+const NKRO_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+    0x09, 0x06,        // Usage (Keyboard)
+    0xA1, 0x01,        // Collection (Application)
+    0x05, 0x07,        //   Usage Page (Kbrd/Keypad)
+    0x19, 0xE0,        //   Usage Minimum (0xE0)
+    0x29, 0xE7,        //   Usage Maximum (0xE7)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x25, 0x01,        //   Logical Maximum (1)
+    0x95, 0x08,        //   Report Count (8)
+    0x75, 0x01,        //   Report Size (1)
+    0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x05, 0x07,        //   Usage Page (Kbrd/Keypad)
+    0x19, 0x00,        //   Usage Minimum (0x00)
+    0x29, 0xFF,        //   Usage Maximum (0xFF)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x25, 0x01,        //   Logical Maximum (1)
+    0x96, 0x00, 0x01,  //   Report Count (256)
+    0x75, 0x01,        //   Report Size (1)
+    0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x05, 0x08,        //   Usage Page (LEDs)
+    0x19, 0x01,        //   Usage Minimum (Num Lock)
+    0x29, 0x05,        //   Usage Maximum (Kana)
+    0x95, 0x05,        //   Report Count (5)
+    0x75, 0x01,        //   Report Size (1)
+    0x91, 0x02,        //   Output (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+    0x95, 0x01,        //   Report Count (1)
+    0x75, 0x03,        //   Report Size (3)
+    0x91, 0x03,        //   Output (Const,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+    0xC0,              // End Collection
+];
+
 /// A keyboard HID device.
+///
+/// By default, it uses the 6 keys rollover boot report. A keyboard
+/// created with [`Keyboard::new_nkro`] uses the N-key rollover
+/// report, falling back to the boot report when the host selects
+/// the boot protocol (as a BIOS does).
 pub struct Keyboard<L> {
     report: KbHidReport,
+    nkro_report: Option<NkroKbHidReport>,
+    protocol_mode: ProtocolMode,
     leds: L,
 }
 
@@ -67,17 +107,76 @@ impl<L> Keyboard<L> {
     pub fn new(leds: L) -> Keyboard<L> {
         Keyboard {
             report: KbHidReport::default(),
+            nkro_report: None,
+            protocol_mode: ProtocolMode::Report,
             leds,
         }
     }
-    /// Set the current keyboard HID report.  Returns `true` if it is modified.
-    pub fn set_keyboard_report(&mut self, report: KbHidReport) -> bool {
-        if report == self.report {
-            false
+
+    /// Creates a new `Keyboard` object using the N-key rollover
+    /// report.
+    pub fn new_nkro(leds: L) -> Keyboard<L> {
+        Keyboard {
+            nkro_report: Some(NkroKbHidReport::default()),
+            ..Self::new(leds)
+        }
+    }
+
+    /// Returns `true` if the N-key rollover report is used, i.e. if
+    /// the keyboard was created with [`Keyboard::new_nkro`] and the
+    /// host didn't select the boot protocol.
+    pub fn is_nkro(&self) -> bool {
+        self.nkro_report.is_some() && self.protocol_mode == ProtocolMode::Report
+    }
+
+    /// Sets the pressed key codes, updating the boot report and the
+    /// N-key rollover report.  Returns `true` if the report in use
+    /// is modified.
+    ///
+    /// The report to send is then given by [`Keyboard::report`].
+    pub fn set_keycodes(&mut self, keycodes: impl IntoIterator<Item = KeyCode>) -> bool {
+        let mut report = KbHidReport::default();
+        let mut nkro_report = NkroKbHidReport::default();
+        for kc in keycodes {
+            report.pressed(kc);
+            nkro_report.pressed(kc);
+        }
+        self.set_reports(report, nkro_report)
+    }
+
+    /// Sets the boot report and the N-key rollover report, returning
+    /// `true` if the report in use is modified.
+    fn set_reports(&mut self, report: KbHidReport, nkro_report: NkroKbHidReport) -> bool {
+        let modified = if self.is_nkro() {
+            self.nkro_report.as_ref() != Some(&nkro_report)
         } else {
-            self.report = report;
-            true
+            self.report != report
+        };
+        self.report = report;
+        if let Some(r) = &mut self.nkro_report {
+            *r = nkro_report;
         }
+        modified
+    }
+
+    /// Returns the bytes of the report in use, the N-key rollover
+    /// report or the boot report.
+    pub fn report(&self) -> &[u8] {
+        match &self.nkro_report {
+            Some(r) if self.protocol_mode == ProtocolMode::Report => r.as_bytes(),
+            _ => self.report.as_bytes(),
+        }
+    }
+
+    /// Set the current keyboard HID report.  Returns `true` if the
+    /// report in use is modified.
+    ///
+    /// The N-key rollover report is set to the keys of the given
+    /// report, and is then limited to 6 keys: use
+    /// [`Keyboard::set_keycodes`] to send more keys.
+    pub fn set_keyboard_report(&mut self, report: KbHidReport) -> bool {
+        let nkro_report = NkroKbHidReport::from(&report);
+        self.set_reports(report, nkro_report)
     }
 
     /// Returns the underlying leds object.
@@ -96,20 +195,32 @@ impl<L: Leds> HidDevice for Keyboard<L> {
     }
 
     fn max_packet_size(&self) -> u16 {
-        8
+        if self.nkro_report.is_some() {
+            64
+        } else {
+            8
+        }
     }
 
     fn report_descriptor(&self) -> &[u8] {
-        REPORT_DESCRIPTOR
+        if self.nkro_report.is_some() {
+            NKRO_REPORT_DESCRIPTOR
+        } else {
+            REPORT_DESCRIPTOR
+        }
     }
 
     fn get_report(&mut self, report_type: ReportType, _report_id: u8) -> Result<&[u8], hid::Error> {
         match report_type {
-            ReportType::Input => Ok(self.report.as_bytes()),
+            ReportType::Input => Ok(self.report()),
             _ => Err(hid::Error),
         }
     }
 
+    fn set_protocol_mode(&mut self, mode: ProtocolMode) {
+        self.protocol_mode = mode;
+    }
+
     fn set_report(
         &mut self,
         report_type: ReportType,
@@ -128,3 +239,78 @@ impl<L: Leds> HidDevice for Keyboard<L> {
         Err(hid::Error)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key_code::KeyCode::*;
+
+    const KEYS: [KeyCode; 8] = [LShift, A, B, C, D, E, F, G];
+
+    #[test]
+    fn boot() {
+        let mut keyboard = Keyboard::new(());
+        assert!(!keyboard.is_nkro());
+        assert_eq!(keyboard.max_packet_size(), 8);
+        assert!(keyboard.set_keycodes(KEYS[..3].iter().copied()));
+        assert!(!keyboard.set_keycodes(KEYS[..3].iter().copied()));
+        assert_eq!(keyboard.report(), &[0x02, 0, 0x04, 0x05, 0, 0, 0, 0]);
+        keyboard.set_keycodes(KEYS.iter().copied());
+        assert_eq!(keyboard.report(), &[0x02, 0, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn nkro() {
+        let mut keyboard = Keyboard::new_nkro(());
+        assert!(keyboard.is_nkro());
+        assert_eq!(keyboard.report_descriptor(), NKRO_REPORT_DESCRIPTOR);
+        assert!(keyboard.set_keycodes(KEYS.iter().copied()));
+        assert!(!keyboard.set_keycodes(KEYS.iter().copied()));
+        let mut report = [0; 33];
+        report.copy_from_slice(keyboard.report());
+        assert_eq!(report[..3], [0x02, 0xF0, 0x07]);
+        assert!(report[3..].iter().all(|b| *b == 0));
+        assert_eq!(
+            keyboard.get_report(ReportType::Input, 0).ok(),
+            Some(&report[..])
+        );
+
+        // fallback to the boot report
+        keyboard.set_protocol_mode(ProtocolMode::Boot);
+        assert!(!keyboard.is_nkro());
+        assert_eq!(keyboard.report(), &[0x02, 0, 1, 1, 1, 1, 1, 1]);
+        assert!(keyboard.set_keycodes([A]));
+        assert_eq!(keyboard.report(), &[0, 0, 0x04, 0, 0, 0, 0, 0]);
+
+        keyboard.set_protocol_mode(ProtocolMode::Report);
+        assert_eq!(keyboard.report()[..3], [0, 0x10, 0]);
+    }
+
+    #[test]
+    fn nkro_keyboard_report() {
+        let mut keyboard = Keyboard::new_nkro(());
+        let report: KbHidReport = KEYS[..3].iter().copied().collect();
+        assert!(keyboard.set_keyboard_report(report.clone()));
+        assert!(!keyboard.set_keyboard_report(report));
+        let mut report = [0; 33];
+        report.copy_from_slice(keyboard.report());
+        assert_eq!(report[..3], [0x02, 0x30, 0]);
+        assert!(report[3..].iter().all(|b| *b == 0));
+        assert_eq!(
+            keyboard.get_report(ReportType::Input, 0).ok(),
+            Some(&report[..])
+        );
+
+        // the error key codes are ignored
+        assert!(keyboard.set_keyboard_report(KEYS.iter().copied().collect()));
+        assert_eq!(keyboard.report()[..3], [0x02, 0, 0]);
+        assert!(keyboard.set_keyboard_report(KbHidReport::default()));
+        assert!(keyboard.report().iter().all(|b| *b == 0));
+
+        keyboard.set_protocol_mode(ProtocolMode::Boot);
+        assert!(keyboard.set_keyboard_report([A].iter().copied().collect()));
+        assert_eq!(keyboard.report(), &[0, 0, 0x04, 0, 0, 0, 0, 0]);
+        keyboard.set_protocol_mode(ProtocolMode::Report);
+        assert_eq!(keyboard.report()[..3], [0, 0x10, 0]);
+    }
+}
@@ -0,0 +1,161 @@
+//! Keymap storage.
+//!
+//! A [`Layout`](crate::layout::Layout) gets the actions of the keys
+//! from a [`Keymap`]. The classic keymap is a static array of
+//! [`Layers`], that can't be modified. A [`RuntimeKeymap`] allows to
+//! change the actions of the keys at runtime, for example to remap
+//! the keys from the host.
+
+use crate::action::Action;
+use crate::encoding::{keycode_action, ActionCode, DecodeError};
+use crate::key_code::KeyCode;
+use crate::layout::Layers;
+
+/// The storage of the actions of the keys.
+pub trait Keymap<T: 'static> {
+    /// Returns the action of the key at the given layer, row and
+    /// column, or `None` if there is no such key.
+    fn action(&self, layer: usize, row: usize, col: usize) -> Option<&'static Action<T>>;
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: 'static> Keymap<T>
+    for &'static Layers<C, R, L, T>
+{
+    fn action(&self, layer: usize, row: usize, col: usize) -> Option<&'static Action<T>> {
+        let layers: &'static Layers<C, R, L, T> = self;
+        layers.get(layer)?.get(row)?.get(col)
+    }
+}
+
+/// A keymap which actions can be changed at runtime.
+///
+/// The generic parameters are in order: the number of columns, rows
+/// and layers, and the type contained in custom actions.
+///
+/// The keymap stores references to the actions, initialized from
+/// static layers, and modified using
+/// [`RuntimeKeymap::set_action`] and [`RuntimeKeymap::set_keycode`].
+///
+/// The actions are `'static` references rather than owned values:
+/// the layout keeps the actions of the pressed keys after the press,
+/// and gives `&'static` references to the values of the custom
+/// actions in its [`CustomEvent`](crate::layout::CustomEvent)s. The
+/// actions set at runtime must then be defined in statics, except
+/// the key codes that are given by `set_keycode`.
+///
+/// Thus, a keymap can't hold an arbitrary action built at runtime.
+/// The actions received from the host are set with
+/// [`RuntimeKeymap::set_code`], that only accepts the actions having
+/// a [16-bit code](crate::encoding) or defined in the table of user
+/// actions, and returns an error for the others.
+///
+/// # Example
+///
+/// ```
+/// use keyberon::action::{k, Action};
+/// use keyberon::key_code::KeyCode;
+/// use keyberon::keymap::{Keymap, RuntimeKeymap};
+/// use keyberon::layout::{Layers, Layout};
+///
+/// static LAYERS: Layers<2, 1, 1> = [[[k(KeyCode::A), k(KeyCode::B)]]];
+/// let mut keymap = RuntimeKeymap::new(&LAYERS);
+/// keymap.set_action(0, 0, 1, &Action::KeyCode(KeyCode::C));
+/// assert_eq!(Some(&k(KeyCode::C)), keymap.action(0, 0, 1));
+///
+/// // Any key code can be set without a static
+/// let kc = KeyCode::E;
+/// keymap.set_keycode(0, 0, 1, kc);
+/// assert_eq!(Some(&k(KeyCode::E)), keymap.action(0, 0, 1));
+///
+/// // An action can be set from its code, if it can be represented
+/// assert_eq!(Ok(()), keymap.set_code(0, 0, 1, 0x0005, &[]));
+/// assert_eq!(Some(&k(KeyCode::B)), keymap.action(0, 0, 1));
+/// assert!(keymap.set_code(0, 0, 1, 0x7E40, &[]).is_err());
+///
+/// // A layout using a runtime keymap
+/// let mut layout = Layout::new_runtime(&LAYERS);
+/// layout.set_action(0, 0, 0, &Action::KeyCode(KeyCode::D));
+/// ```
+pub struct RuntimeKeymap<
+    const C: usize,
+    const R: usize,
+    const L: usize,
+    T: 'static = core::convert::Infallible,
+> {
+    layers: [[[&'static Action<T>; C]; R]; L],
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: 'static> RuntimeKeymap<C, R, L, T> {
+    /// Creates a new `RuntimeKeymap` with the actions of the given
+    /// layers.
+    pub fn new(layers: &'static Layers<C, R, L, T>) -> Self {
+        Self {
+            layers: core::array::from_fn(|l| {
+                core::array::from_fn(|r| core::array::from_fn(|c| &layers[l][r][c]))
+            }),
+        }
+    }
+
+    /// Sets the action of the key at the given layer, row and column.
+    ///
+    /// Does nothing if there is no such key.
+    pub fn set_action(&mut self, layer: usize, row: usize, col: usize, action: &'static Action<T>) {
+        if let Some(a) = self
+            .layers
+            .get_mut(layer)
+            .and_then(|l| l.get_mut(row))
+            .and_then(|r| r.get_mut(col))
+        {
+            *a = action;
+        }
+    }
+
+    /// Sets the key code of the key at the given layer, row and
+    /// column, as an [`Action::KeyCode`].
+    ///
+    /// Does nothing if there is no such key.
+    pub fn set_keycode(&mut self, layer: usize, row: usize, col: usize, keycode: KeyCode) {
+        self.set_action(layer, row, col, keycode_action(keycode));
+    }
+
+    /// Sets the action of the key at the given layer, row and column
+    /// from its [16-bit code](crate::encoding).
+    ///
+    /// The user actions are taken from the given table. The keymap is
+    /// not modified on error, that is returned if the code doesn't
+    /// correspond to any action, if it is a user action out of the
+    /// table, or if there is no such key.
+    pub fn set_code(
+        &mut self,
+        layer: usize,
+        row: usize,
+        col: usize,
+        code: u16,
+        user_actions: &'static [Action<T>],
+    ) -> Result<(), DecodeError> {
+        if layer >= L || row >= R || col >= C {
+            return Err(DecodeError::InvalidKey);
+        }
+        let action = ActionCode::decode(code)?.to_action(user_actions)?;
+        self.set_action(layer, row, col, action);
+        Ok(())
+    }
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: 'static> Clone
+    for RuntimeKeymap<C, R, L, T>
+{
+    fn clone(&self) -> Self {
+        Self {
+            layers: self.layers,
+        }
+    }
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: 'static> Keymap<T>
+    for RuntimeKeymap<C, R, L, T>
+{
+    fn action(&self, layer: usize, row: usize, col: usize) -> Option<&'static Action<T>> {
+        self.layers.get(layer)?.get(row)?.get(col).copied()
+    }
+}
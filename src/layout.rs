@@ -15,6 +15,8 @@
 /// - [`Action::Trans`]: Lowercase `t`
 /// - [`Action::Layer`]: A number in parentheses: `(1)`, `(4 - 2)`, `(0x4u8 as usize)`
 /// - [`Action::MultipleActions`]: Actions in brackets: `[LCtrl S]`, `[LAlt LCtrl C]`, `[(2) B {Action::NoOp}]`
+/// - [`Action::Sequence`]: String literals type the string, pressing `LShift` when needed: `"Hello!\n"`.
+///   Only characters available on a US layout are supported.
 /// - Other `Action`s: anything in braces (`{}`) is copied unchanged to the final layout - `{ Action::Custom(42) }`
 ///   simply becomes `Action::Custom(42)`
 ///
@@ -46,8 +48,15 @@
 /// ```
 pub use keyberon_macros::*;
 
-use crate::action::{Action, HoldTapConfig, HoldTapAction};
+use crate::action::{
+    Action, CapsWordConfig, HoldTapAction, HoldTapConfig, LeaderConfig, ModMorphAction,
+    OneShotAction, OneShotLayerAction, SequenceEvent, TapDanceAction,
+};
+use crate::consumer::ConsumerCode;
 use crate::key_code::KeyCode;
+use crate::keymap::{Keymap, RuntimeKeymap};
+use crate::mouse::{MouseConfig, MouseKey, MouseReport, MouseState};
+use crate::system::SystemCode;
 use arraydeque::ArrayDeque;
 use heapless::Vec;
 
@@ -63,23 +72,124 @@ use State::*;
 pub type Layers<const C: usize, const R: usize, const L: usize, T = core::convert::Infallible> =
     [[[Action<T>; C]; R]; L];
 
+/// A layer activated when some other layers are active.
+///
+/// For example, the classic tri-layer "when the lower and raise layers
+/// are active, the adjust layer is active" is
+/// `ConditionalLayer { layers: &[LOWER, RAISE], layer: ADJUST }`. See
+/// [`Layout::set_conditional_layers`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ConditionalLayer {
+    /// The layers that must all be active.
+    pub layers: &'static [usize],
+    /// The layer activated.
+    pub layer: usize,
+}
+
+/// Configuration of auto shift (see [`Layout::set_auto_shift`]).
+///
+/// When one of the `keycodes` is held more than `timeout` ticks,
+/// its shifted version is sent instead of the plain key code. The
+/// key codes pressed while a modifier other than shift is held are
+/// not auto shifted, keeping the shortcuts as Ctrl+C.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AutoShiftConfig {
+    /// The duration, in ticks (usually milliseconds), after which
+    /// the shifted key code is sent.
+    pub timeout: u16,
+    /// The key codes that are auto shifted.
+    pub keycodes: &'static [KeyCode],
+    /// The duration, in ticks, after the shifted key code is sent,
+    /// after which it is pressed again and kept pressed while the key
+    /// is held, allowing the host to auto-repeat it.
+    ///
+    /// To deactivate the auto-repeat, set this to 0: the shifted key
+    /// code is only tapped.
+    pub repeat: u16,
+}
+
+/// How the active layers define the action of a key.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum LayerMode {
+    /// The last activated layer is the current layer, and
+    /// [`Action::Trans`] gets the action of the default layer.
+    #[default]
+    Last,
+    /// The active layers are stacked, as in QMK: the active layer
+    /// with the highest index is the current layer, and
+    /// [`Action::Trans`] gets the action of the next lower active
+    /// layer. Only the first 32 layers can be used in this mode.
+    Stacked,
+}
+
 /// The current event stack.
 ///
 /// Events can be retrieved by iterating over this struct and calling [Stacked::event].
 type Stack = ArrayDeque<[Stacked; 16], arraydeque::behavior::Wrapping>;
 
+/// The events pushed out of a full [`Stack`] by the releases added
+/// at its front, waiting for some room in the stack.
+type Overflow = ArrayDeque<[Stacked; 16], arraydeque::behavior::Saturating>;
+
+/// Adds the release of the given key at the front of the stack, to
+/// be processed before the other events. If the stack is full, its
+/// last event is pushed out to the overflow.
+fn push_release(stacked: &mut Stack, overflow: &mut Overflow, (i, j): (u8, u8)) {
+    if let Some(last) = stacked.push_front(Event::Release(i, j).into()) {
+        let _ = overflow.push_front(last);
+    }
+}
+
+/// The key codes of the layout: the ones of the 64 states, and the
+/// shift added by caps word.
+type KeyCodes = Vec<KeyCode, 65>;
+
+/// A dynamic macro, recorded at runtime.
+type DynamicMacro = Vec<SequenceEvent, 64>;
+
+/// The sequences being played, the first one being the current one.
+type Sequences = ArrayDeque<[SequenceState; 4], arraydeque::behavior::Saturating>;
+
 /// The layout manager. It takes `Event`s and `tick`s as input, and
 /// generate keyboard reports.
-pub struct Layout<const C: usize, const R: usize, const L: usize, T = core::convert::Infallible>
-where
+///
+/// The actions of the keys are given by a [`Keymap`], by default
+/// static [`Layers`].
+pub struct Layout<
+    const C: usize,
+    const R: usize,
+    const L: usize,
+    T = core::convert::Infallible,
+    K = &'static Layers<C, R, L, T>,
+> where
     T: 'static,
 {
-    layers: &'static [[[Action<T>; C]; R]; L],
+    keymap: K,
     default_layer: usize,
     states: Vec<State<T>, 64>,
     waiting: Option<WaitingState<T>>,
     stacked: Stack,
+    overflow: Overflow,
     tap_hold_tracker: TapHoldTracker,
+    oneshots: Vec<OneShotState, 8>,
+    toggled_layers: Vec<usize, 8>,
+    sequences: Sequences,
+    caps_word: Option<CapsWordState>,
+    leader: Option<LeaderState<T>>,
+    conditional_layers: &'static [ConditionalLayer],
+    layer_mode: LayerMode,
+    /// The held HoldTap key and its tap action, if the tap action
+    /// must be performed on release.
+    retro_tap: Option<((u8, u8), &'static Action<T>)>,
+    auto_shift: Option<&'static AutoShiftConfig>,
+    /// The last pressed key code that is not a modifier, with the
+    /// modifier bitfield active at this time.
+    last_keycode: Option<(KeyCode, u8)>,
+    dynamic_macros: [DynamicMacro; 2],
+    recording: Option<RecordingState>,
+    dynamic_macro_overflowed: bool,
+    mouse_config: &'static MouseConfig,
+    mouse: MouseState,
 }
 
 /// An event on the key matrix.
@@ -141,9 +251,10 @@ impl Event {
 }
 
 /// Event from custom action.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Default)]
 pub enum CustomEvent<T: 'static> {
     /// No custom action.
+    #[default]
     NoEvent,
     /// The given custom action key is pressed.
     Press(&'static T),
@@ -164,17 +275,50 @@ impl<T> CustomEvent<T> {
         }
     }
 }
-impl<T> Default for CustomEvent<T> {
-    fn default() -> Self {
-        CustomEvent::NoEvent
-    }
-}
 
 #[derive(Debug, Eq, PartialEq)]
 enum State<T: 'static> {
-    NormalKey { keycode: KeyCode, coord: (u8, u8) },
-    LayerModifier { value: usize, coord: (u8, u8) },
-    Custom { value: &'static T, coord: (u8, u8) },
+    NormalKey {
+        keycode: KeyCode,
+        coord: (u8, u8),
+    },
+    LayerModifier {
+        value: usize,
+        coord: (u8, u8),
+    },
+    Custom {
+        value: &'static T,
+        coord: (u8, u8),
+    },
+    /// A key code pressed by a sequence, not associated with a key.
+    FakeKey {
+        keycode: KeyCode,
+    },
+    /// A key code sent by an auto shifted key: pressed during one
+    /// tick, and pressed again after `repeat` ticks if not 0.
+    AutoShift {
+        keycode: KeyCode,
+        coord: (u8, u8),
+        since: u16,
+        repeat: u16,
+    },
+    /// Modifiers removed from the key codes by a mod-morph key.
+    SuppressedMods {
+        mods: u8,
+        coord: (u8, u8),
+    },
+    Consumer {
+        code: ConsumerCode,
+        coord: (u8, u8),
+    },
+    System {
+        code: SystemCode,
+        coord: (u8, u8),
+    },
+    Mouse {
+        key: MouseKey,
+        coord: (u8, u8),
+    },
 }
 impl<T> Copy for State<T> {}
 impl<T> Clone for State<T> {
@@ -185,16 +329,51 @@ impl<T> Clone for State<T> {
 impl<T: 'static> State<T> {
     fn keycode(&self) -> Option<KeyCode> {
         match self {
-            NormalKey { keycode, .. } => Some(*keycode),
+            NormalKey { keycode, .. } | FakeKey { keycode } => Some(*keycode),
+            AutoShift {
+                keycode,
+                since,
+                repeat,
+                ..
+            } if *since == 0 || (*repeat != 0 && since >= repeat) => Some(*keycode),
+            _ => None,
+        }
+    }
+    fn fake_keycode(&self) -> Option<KeyCode> {
+        match self {
+            FakeKey { keycode } => Some(*keycode),
             _ => None,
         }
     }
     fn tick(&self) -> Option<Self> {
-        Some(*self)
+        match *self {
+            AutoShift {
+                keycode,
+                coord,
+                since,
+                repeat,
+            } => Some(AutoShift {
+                keycode,
+                coord,
+                since: since.saturating_add(1),
+                repeat,
+            }),
+            _ => Some(*self),
+        }
     }
     fn release(&self, c: (u8, u8), custom: &mut CustomEvent<T>) -> Option<Self> {
         match *self {
-            NormalKey { coord, .. } | LayerModifier { coord, .. } if coord == c => None,
+            NormalKey { coord, .. }
+            | LayerModifier { coord, .. }
+            | AutoShift { coord, .. }
+            | SuppressedMods { coord, .. }
+            | Consumer { coord, .. }
+            | System { coord, .. }
+            | Mouse { coord, .. }
+                if coord == c =>
+            {
+                None
+            }
             Custom { value, coord } if coord == c => {
                 custom.update(CustomEvent::Release(value));
                 None
@@ -208,6 +387,24 @@ impl<T: 'static> State<T> {
             _ => None,
         }
     }
+    fn consumer_code(&self) -> Option<ConsumerCode> {
+        match self {
+            Consumer { code, .. } => Some(*code),
+            _ => self.keycode().and_then(ConsumerCode::from_keycode),
+        }
+    }
+    fn system_code(&self) -> Option<SystemCode> {
+        match self {
+            System { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+    fn mouse_key(&self) -> Option<MouseKey> {
+        match self {
+            Mouse { key, .. } => Some(*key),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -215,9 +412,26 @@ struct WaitingState<T: 'static> {
     coord: (u8, u8),
     timeout: u16,
     delay: u16,
-    hold: &'static Action<T>,
-    tap: &'static Action<T>,
-    config: HoldTapConfig,
+    config: WaitingConfig<T>,
+}
+
+/// The action waiting for a decision.
+#[derive(Debug)]
+enum WaitingConfig<T: 'static> {
+    HoldTap {
+        hold: &'static Action<T>,
+        tap: &'static Action<T>,
+        config: HoldTapConfig,
+        retro_tap: bool,
+    },
+    TapDance(&'static TapDanceAction<T>),
+    AutoShift(KeyCode),
+}
+impl<T> Copy for WaitingConfig<T> {}
+impl<T> Clone for WaitingConfig<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
 /// Actions that can be triggered for a key configured for HoldTap.
@@ -234,7 +448,12 @@ pub enum WaitingAction {
 impl<T> WaitingState<T> {
     fn tick(&mut self, stacked: &Stack) -> Option<WaitingAction> {
         self.timeout = self.timeout.saturating_sub(1);
-        match self.config {
+        let config = match self.config {
+            WaitingConfig::HoldTap { config, .. } => config,
+            WaitingConfig::AutoShift(_) => HoldTapConfig::Default,
+            WaitingConfig::TapDance(td) => return self.tap_dance_tick(td.timeout, stacked),
+        };
+        match config {
             HoldTapConfig::Default => (),
             HoldTapConfig::HoldOnOtherKeyPress => {
                 if stacked.iter().any(|s| s.event.is_press()) {
@@ -273,6 +492,39 @@ impl<T> WaitingState<T> {
             None
         }
     }
+    /// The tap dance ends when another key is pressed, or when no
+    /// press or release of the tap dance key happened during
+    /// `window` ticks. The result is `Hold` if the key is still
+    /// pressed at this time, `Tap` otherwise.
+    fn tap_dance_tick(&self, window: u16, stacked: &Stack) -> Option<WaitingAction> {
+        let mut held = true;
+        let mut since_last = None;
+        for s in stacked.iter() {
+            match s.event {
+                Event::Press(i, j) if (i, j) == self.coord => held = true,
+                Event::Release(i, j) if (i, j) == self.coord => held = false,
+                Event::Press(..) => return Some(Self::tap_dance_result(held)),
+                Event::Release(..) => continue,
+            }
+            since_last = Some(s.since);
+        }
+        let ended = match since_last {
+            Some(since) => since >= window,
+            None => self.timeout == 0,
+        };
+        if ended {
+            Some(Self::tap_dance_result(held))
+        } else {
+            None
+        }
+    }
+    fn tap_dance_result(held: bool) -> WaitingAction {
+        if held {
+            WaitingAction::Hold
+        } else {
+            WaitingAction::Tap
+        }
+    }
     fn is_corresponding_release(&self, event: &Event) -> bool {
         matches!(event, Event::Release(i, j) if (*i, *j) == self.coord)
     }
@@ -315,39 +567,322 @@ impl Stacked {
     }
 }
 
-#[derive(Default)]
 struct TapHoldTracker {
     coord: (u8, u8),
     timeout: u16,
+    /// The ticks since the last press of a key that is not a modifier.
+    since_key_press: u16,
+}
+
+impl Default for TapHoldTracker {
+    fn default() -> Self {
+        Self {
+            coord: (0, 0),
+            timeout: 0,
+            since_key_press: u16::MAX,
+        }
+    }
 }
 
 impl TapHoldTracker {
     fn tick(&mut self) {
         self.timeout = self.timeout.saturating_sub(1);
+        self.since_key_press = self.since_key_press.saturating_add(1);
+    }
+}
+
+/// A sequence being played.
+#[derive(Debug)]
+struct SequenceState {
+    events: SequenceEvents,
+    delay: u16,
+    /// The key code tapped at the previous tick, to be released.
+    tapped: Option<KeyCode>,
+}
+
+/// The remaining events of a sequence being played.
+#[derive(Debug)]
+enum SequenceEvents {
+    Static {
+        events: &'static [SequenceEvent],
+        pos: usize,
+    },
+    DynamicMacro {
+        slot: usize,
+        pos: usize,
+    },
+}
+
+/// A dynamic macro being recorded.
+#[derive(Debug)]
+struct RecordingState {
+    slot: usize,
+    /// The key codes at the previous tick.
+    keycodes: KeyCodes,
+    /// The ticks since the last recorded change.
+    idle: u16,
+}
+
+/// A one shot action or layer waiting for the next key press.
+#[derive(Debug)]
+struct OneShotState {
+    coord: (u8, u8),
+    timeout: u16,
+    released: bool,
+    layer: bool,
+    /// The coordinates of the key that used the one shot, if any.
+    used_by: Option<(u8, u8)>,
+}
+
+/// The active caps word.
+#[derive(Debug)]
+struct CapsWordState {
+    config: &'static CapsWordConfig,
+    /// The remaining ticks before deactivation.
+    timeout: u16,
+}
+
+impl CapsWordState {
+    fn new(config: &'static CapsWordConfig) -> Self {
+        Self {
+            config,
+            timeout: config.timeout,
+        }
+    }
+    /// Returns `true` if the key code is shifted by caps word.
+    fn is_shifted(&self, keycode: KeyCode) -> bool {
+        (KeyCode::A <= keycode && keycode <= KeyCode::Z) || self.config.shifted.contains(&keycode)
+    }
+    /// Returns `true` if the key code does not end the word.
+    fn is_continuing(&self, keycode: KeyCode) -> bool {
+        keycode.is_modifier()
+            || self.is_shifted(keycode)
+            || self.config.continuing.contains(&keycode)
+    }
+    /// Returns `true` if caps word must be deactivated.
+    fn tick(&mut self) -> bool {
+        if self.config.timeout == 0 {
+            return false;
+        }
+        self.timeout = self.timeout.saturating_sub(1);
+        self.timeout == 0
+    }
+}
+
+/// The active leader key, capturing the key presses.
+#[derive(Debug)]
+struct LeaderState<T: 'static> {
+    config: &'static LeaderConfig<T>,
+    coord: (u8, u8),
+    keys: Vec<KeyCode, 8>,
+    /// The remaining ticks before failure.
+    timeout: u16,
+}
+
+impl<T> LeaderState<T> {
+    fn new(config: &'static LeaderConfig<T>, coord: (u8, u8)) -> Self {
+        Self {
+            config,
+            coord,
+            keys: Vec::new(),
+            timeout: config.timeout,
+        }
+    }
+    /// Returns `true` if the capture failed by timeout.
+    fn tick(&mut self) -> bool {
+        if self.config.timeout == 0 {
+            return false;
+        }
+        self.timeout = self.timeout.saturating_sub(1);
+        self.timeout == 0
+    }
+}
+
+impl OneShotState {
+    fn new(coord: (u8, u8), timeout: u16, layer: bool) -> Self {
+        Self {
+            coord,
+            timeout,
+            released: false,
+            layer,
+            used_by: None,
+        }
+    }
+    /// Returns `true` if the one shot must be released.
+    fn tick(&mut self) -> bool {
+        if !self.released || self.used_by.is_some() || self.timeout == 0 {
+            return false;
+        }
+        self.timeout -= 1;
+        self.timeout == 0
     }
 }
 
 impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L, T> {
     /// Creates a new `Layout` object.
     pub fn new(layers: &'static [[[Action<T>; C]; R]; L]) -> Self {
+        Self::new_with_keymap(layers)
+    }
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: 'static>
+    Layout<C, R, L, T, RuntimeKeymap<C, R, L, T>>
+{
+    /// Creates a new `Layout` object with a keymap that can be
+    /// modified at runtime, initialized with the given layers.
+    pub fn new_runtime(layers: &'static Layers<C, R, L, T>) -> Self {
+        Self::new_with_keymap(RuntimeKeymap::new(layers))
+    }
+    /// Sets the action of the key at the given layer, row and column
+    /// (see [`RuntimeKeymap::set_action`]).
+    ///
+    /// The keys already pressed are not modified.
+    pub fn set_action(&mut self, layer: usize, row: usize, col: usize, action: &'static Action<T>) {
+        self.keymap.set_action(layer, row, col, action);
+    }
+    /// Sets the key code of the key at the given layer, row and
+    /// column (see [`RuntimeKeymap::set_keycode`]).
+    ///
+    /// The keys already pressed are not modified.
+    pub fn set_keycode(&mut self, layer: usize, row: usize, col: usize, keycode: KeyCode) {
+        self.keymap.set_keycode(layer, row, col, keycode);
+    }
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: 'static, K: Keymap<T>>
+    Layout<C, R, L, T, K>
+{
+    /// Creates a new `Layout` object using the given keymap.
+    pub fn new_with_keymap(keymap: K) -> Self {
         Self {
-            layers,
+            keymap,
             default_layer: 0,
             states: Vec::new(),
             waiting: None,
             stacked: ArrayDeque::new(),
+            overflow: ArrayDeque::new(),
             tap_hold_tracker: Default::default(),
+            oneshots: Vec::new(),
+            toggled_layers: Vec::new(),
+            sequences: ArrayDeque::new(),
+            caps_word: None,
+            leader: None,
+            conditional_layers: &[],
+            layer_mode: LayerMode::Last,
+            retro_tap: None,
+            auto_shift: None,
+            last_keycode: None,
+            dynamic_macros: [Vec::new(), Vec::new()],
+            recording: None,
+            dynamic_macro_overflowed: false,
+            mouse_config: &MouseConfig::DEFAULT,
+            mouse: MouseState::default(),
         }
     }
     /// Iterates on the key codes of the current state.
+    ///
+    /// The `KeyCode::Media*` key codes are not included, as they are
+    /// given by [`Layout::consumer_codes`].
     pub fn keycodes(&self) -> impl Iterator<Item = KeyCode> + '_ {
-        self.states.iter().filter_map(State::keycode)
+        self.all_keycodes()
+            .filter(|&kc| ConsumerCode::from_keycode(kc).is_none())
+    }
+    /// Iterates on the consumer codes of the current state, from
+    /// [`Action::Consumer`] and the `KeyCode::Media*` key codes.
+    pub fn consumer_codes(&self) -> impl Iterator<Item = ConsumerCode> + '_ {
+        self.states.iter().filter_map(State::consumer_code)
+    }
+    /// Iterates on the system codes of the current state, from
+    /// [`Action::System`].
+    pub fn system_codes(&self) -> impl Iterator<Item = SystemCode> + '_ {
+        self.states.iter().filter_map(State::system_code)
+    }
+    /// Returns the mouse report of the current tick, from
+    /// [`Action::Mouse`].
+    ///
+    /// The cursor and the wheel only move on some ticks, so the
+    /// report must be sent with a [`Mouse`](crate::mouse::Mouse)
+    /// device after every tick.
+    pub fn mouse_report(&self) -> MouseReport {
+        let buttons =
+            self.states
+                .iter()
+                .filter_map(State::mouse_key)
+                .fold(0, |acc, key| match key {
+                    MouseKey::Button(b) => acc | b as u8,
+                    _ => acc,
+                });
+        self.mouse.report(buttons)
+    }
+    /// Iterates on the key codes of the current state, including the
+    /// media key codes.
+    fn all_keycodes(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        // Only the last pressed key is shifted, as the shift applies
+        // to all the keys of the report: a digit pressed while a
+        // letter is held must not be sent as a symbol.
+        let shift = self.caps_word.as_ref().is_some_and(|cw| {
+            self.states
+                .iter()
+                .filter_map(State::keycode)
+                .rfind(|kc| !kc.is_modifier())
+                .is_some_and(|kc| cw.is_shifted(kc))
+        });
+        self.states
+            .iter()
+            .filter(move |s| !self.is_suppressed(s))
+            .filter_map(State::keycode)
+            .chain(if shift { Some(KeyCode::LShift) } else { None })
+    }
+    /// Returns `true` if a modifier other than shift is active.
+    fn is_shortcut_mod_active(&self) -> bool {
+        self.keycodes()
+            .any(|kc| kc.is_modifier() && kc != KeyCode::LShift && kc != KeyCode::RShift)
+    }
+    /// Returns `true` if the state is a modifier removed by a
+    /// mod-morph key.
+    fn is_suppressed(&self, state: &State<T>) -> bool {
+        let (keycode, c) = match *state {
+            NormalKey { keycode, coord } => (keycode, Some(coord)),
+            FakeKey { keycode } => (keycode, None),
+            _ => return false,
+        };
+        let bit = keycode.as_modifier_bit();
+        bit != 0
+            && self.states.iter().any(|s| {
+                matches!(*s, SuppressedMods { mods, coord } if Some(coord) != c && mods & bit != 0)
+            })
     }
     fn waiting_into_hold(&mut self) -> CustomEvent<T> {
-        if let Some(w) = &self.waiting {
-            let hold = w.hold;
+        if let Some(w) = self.waiting.take() {
             let coord = w.coord;
-            self.waiting = None;
+            let hold = match w.config {
+                WaitingConfig::HoldTap {
+                    hold,
+                    tap,
+                    retro_tap,
+                    ..
+                } => {
+                    // Retro tap is possible only if no other key was pressed.
+                    if retro_tap && !self.stacked.iter().any(|s| s.event.is_press()) {
+                        self.retro_tap = Some((coord, tap));
+                    }
+                    hold
+                }
+                WaitingConfig::TapDance(td) => self.end_tap_dance(td, coord, true),
+                WaitingConfig::AutoShift(keycode) => {
+                    let repeat = self.auto_shift.map_or(0, |config| config.repeat);
+                    for keycode in [KeyCode::LShift, keycode] {
+                        self.keycode_pressed(keycode, coord);
+                        let _ = self.states.push(AutoShift {
+                            keycode,
+                            coord,
+                            since: 0,
+                            repeat,
+                        });
+                    }
+                    return CustomEvent::NoEvent;
+                }
+            };
             if coord == self.tap_hold_tracker.coord {
                 self.tap_hold_tracker.timeout = 0;
             }
@@ -357,15 +892,59 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
         }
     }
     fn waiting_into_tap(&mut self) -> CustomEvent<T> {
-        if let Some(w) = &self.waiting {
-            let tap = w.tap;
+        if let Some(w) = self.waiting.take() {
             let coord = w.coord;
-            self.waiting = None;
+            let tap = match w.config {
+                WaitingConfig::HoldTap { tap, .. } => tap,
+                WaitingConfig::TapDance(td) => self.end_tap_dance(td, coord, false),
+                WaitingConfig::AutoShift(keycode) => {
+                    self.keycode_pressed(keycode, coord);
+                    let _ = self.states.push(NormalKey { coord, keycode });
+                    return CustomEvent::NoEvent;
+                }
+            };
             self.do_action(tap, coord, 0)
         } else {
             CustomEvent::NoEvent
         }
     }
+    /// Removes the events of the tap dance from the stack, and
+    /// returns the action to perform.
+    ///
+    /// If the key is not `held`, its last release is kept at the
+    /// front of the stack to release the action.
+    fn end_tap_dance(
+        &mut self,
+        td: &'static TapDanceAction<T>,
+        coord: (u8, u8),
+        held: bool,
+    ) -> &'static Action<T> {
+        let mut count = 1;
+        let mut i = 0;
+        while let Some(s) = self.stacked.get(i) {
+            match s.event {
+                e if e.coord() == coord => {
+                    if e.is_press() {
+                        count += 1;
+                    }
+                    self.stacked.remove(i);
+                }
+                Event::Press(..) => break,
+                Event::Release(..) => i += 1,
+            }
+        }
+        if !held {
+            push_release(&mut self.stacked, &mut self.overflow, coord);
+        }
+        match &td.hold {
+            Some(hold) if held => hold,
+            _ => td
+                .actions
+                .get(count - 1)
+                .or_else(|| td.actions.last())
+                .unwrap_or(&Action::NoOp),
+        }
+    }
     fn drop_waiting(&mut self) -> CustomEvent<T> {
         self.waiting = None;
         CustomEvent::NoEvent
@@ -377,10 +956,34 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
     /// Returns the corresponding `CustomEvent`, allowing to manage
     /// custom actions thanks to the `Action::Custom` variant.
     pub fn tick(&mut self) -> CustomEvent<T> {
+        self.record_dynamic_macro();
         self.states = self.states.iter().filter_map(State::tick).collect();
-        self.stacked.iter_mut().for_each(Stacked::tick);
+        self.play_sequence();
+        self.restack();
+        self.stacked
+            .iter_mut()
+            .chain(self.overflow.iter_mut())
+            .for_each(Stacked::tick);
         self.tap_hold_tracker.tick();
-        match &mut self.waiting {
+        if self.caps_word.as_mut().is_some_and(CapsWordState::tick) {
+            self.caps_word = None;
+        }
+        for os in self.oneshots.iter_mut() {
+            if os.tick() {
+                os.used_by = Some(os.coord);
+                push_release(&mut self.stacked, &mut self.overflow, os.coord);
+            }
+        }
+        // The leader ends before the event of this tick. Its failure
+        // action is performed after the event, as its release is
+        // added at the front of the stack.
+        let failed_leader =
+            if self.waiting.is_none() && self.leader.as_mut().is_some_and(LeaderState::tick) {
+                self.leader.take()
+            } else {
+                None
+            };
+        let mut custom = match &mut self.waiting {
             Some(w) => match w.tick(&self.stacked) {
                 Some(WaitingAction::Hold) => self.waiting_into_hold(),
                 Some(WaitingAction::Tap) => self.waiting_into_tap(),
@@ -391,29 +994,269 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
                 Some(s) => self.unstack(s),
                 None => CustomEvent::NoEvent,
             },
+        };
+        if let Some(leader) = failed_leader {
+            custom.update(self.leader_failure(leader));
         }
+        // The mouse moves with the keys pressed during this tick.
+        let keys = self.states.iter().filter_map(State::mouse_key);
+        self.mouse.tick(self.mouse_config, keys);
+        custom
     }
     fn unstack(&mut self, stacked: Stacked) -> CustomEvent<T> {
         use Event::*;
         match stacked.event {
             Release(i, j) => {
-                let mut custom = CustomEvent::NoEvent;
-                self.states = self
-                    .states
+                if let Some(pos) = self.oneshots.iter().position(|os| os.coord == (i, j)) {
+                    if self.oneshots[pos].used_by.is_none() {
+                        self.oneshots[pos].released = true;
+                        return CustomEvent::NoEvent;
+                    }
+                    self.oneshots.swap_remove(pos);
+                }
+                let mut custom = self.release((i, j));
+                // The one shot layers used by this key are also released.
+                while let Some(pos) = self
+                    .oneshots
                     .iter()
-                    .filter_map(|s| s.release((i, j), &mut custom))
-                    .collect();
+                    .position(|os| os.layer && os.released && os.used_by == Some((i, j)))
+                {
+                    let os = self.oneshots.swap_remove(pos);
+                    custom.update(self.release(os.coord));
+                }
+                if let Some((coord, tap)) = self.retro_tap {
+                    if coord == (i, j) {
+                        self.retro_tap = None;
+                        push_release(&mut self.stacked, &mut self.overflow, coord);
+                        custom.update(self.do_action(tap, coord, 0));
+                    }
+                }
                 custom
             }
             Press(i, j) => {
+                self.retro_tap = None;
                 let action = self.press_as_action((i, j), self.current_layer());
+                if !matches!(action, Action::OneShot(_) | Action::OneShotLayer(_)) {
+                    for os in self.oneshots.iter_mut() {
+                        if os.layer && os.used_by.is_none() {
+                            os.used_by = Some((i, j));
+                        }
+                    }
+                }
                 self.do_action(action, (i, j), stacked.since)
             }
         }
     }
+    /// Plays the next event of the current sequence.
+    fn play_sequence(&mut self) {
+        let seq = match self.sequences.front_mut() {
+            Some(seq) => seq,
+            None => return,
+        };
+        if let Some(keycode) = seq.tapped.take() {
+            self.states.retain(|s| s.fake_keycode() != Some(keycode));
+            return;
+        }
+        if seq.delay > 0 {
+            seq.delay -= 1;
+            return;
+        }
+        let event = match &mut seq.events {
+            SequenceEvents::Static { events, pos } => {
+                *pos += 1;
+                events.get(*pos - 1).copied()
+            }
+            SequenceEvents::DynamicMacro { slot, pos } => {
+                *pos += 1;
+                self.dynamic_macros[*slot].get(*pos - 1).copied()
+            }
+        };
+        match event {
+            Some(event) => match event {
+                SequenceEvent::Press(keycode) => {
+                    let _ = self.states.push(FakeKey { keycode });
+                }
+                SequenceEvent::Release(keycode) => {
+                    self.states.retain(|s| s.fake_keycode() != Some(keycode));
+                }
+                SequenceEvent::Tap(keycode) => {
+                    if self.states.push(FakeKey { keycode }).is_ok() {
+                        seq.tapped = Some(keycode);
+                    }
+                }
+                SequenceEvent::Delay(delay) => seq.delay = delay.saturating_sub(1),
+            },
+            None => {
+                // The key codes still pressed by the sequence are
+                // released.
+                let events = match seq.events {
+                    SequenceEvents::Static { events, .. } => events,
+                    SequenceEvents::DynamicMacro { slot, .. } => &self.dynamic_macros[slot][..],
+                };
+                for event in events {
+                    if let SequenceEvent::Press(keycode) = *event {
+                        self.states.retain(|s| s.fake_keycode() != Some(keycode));
+                    }
+                }
+                self.sequences.pop_front();
+            }
+        }
+    }
+    /// Records the key code changes since the previous tick in the
+    /// dynamic macro being recorded, preceded by the time elapsed
+    /// since the previous change.
+    fn record_dynamic_macro(&mut self) {
+        let rec = match &self.recording {
+            Some(rec) => rec,
+            None => return,
+        };
+        let keycodes: KeyCodes = self.all_keycodes().collect();
+        let buffer = &mut self.dynamic_macros[rec.slot];
+        let released = rec.keycodes.iter().filter(|kc| !keycodes.contains(kc));
+        let pressed = keycodes.iter().filter(|kc| !rec.keycodes.contains(kc));
+        let mut events = released
+            .map(|&kc| SequenceEvent::Release(kc))
+            .chain(pressed.map(|&kc| SequenceEvent::Press(kc)))
+            .peekable();
+        if events.peek().is_none() {
+            if let Some(rec) = &mut self.recording {
+                rec.idle = rec.idle.saturating_add(1);
+            }
+            return;
+        }
+        // Playing an event takes a tick, the delay is the idle ticks
+        // in between. The time before the first change is ignored.
+        let delay = Some(rec.idle)
+            .filter(|&idle| idle > 0 && !buffer.is_empty())
+            .map(SequenceEvent::Delay);
+        for event in delay.into_iter().chain(events) {
+            if buffer.push(event).is_err() {
+                self.dynamic_macro_overflowed = true;
+                self.recording = None;
+                return;
+            }
+        }
+        if let Some(rec) = &mut self.recording {
+            rec.keycodes = keycodes;
+            rec.idle = 0;
+        }
+    }
+    fn release(&mut self, coord: (u8, u8)) -> CustomEvent<T> {
+        let mut custom = CustomEvent::NoEvent;
+        self.states = self
+            .states
+            .iter()
+            .filter_map(|s| s.release(coord, &mut custom))
+            .collect();
+        custom
+    }
+    /// Marks the waiting one shot actions as used, as a non modifier
+    /// key press just happened. The released ones will be released at the next
+    /// tick.
+    fn use_oneshots(&mut self, coord: (u8, u8)) {
+        for os in self.oneshots.iter_mut() {
+            if os.layer || os.used_by.is_some() {
+                continue;
+            }
+            os.used_by = Some(coord);
+            if os.released {
+                push_release(&mut self.stacked, &mut self.overflow, os.coord);
+            }
+        }
+    }
+    /// Updates caps word on a key code press: the timeout is reset,
+    /// or caps word is deactivated if the key code ends the word.
+    fn update_caps_word(&mut self, keycode: KeyCode) {
+        if let Some(cw) = &mut self.caps_word {
+            if cw.is_continuing(keycode) {
+                cw.timeout = cw.config.timeout;
+            } else {
+                self.caps_word = None;
+            }
+        }
+    }
+    /// Captures a key code pressed after the leader key, performing
+    /// the action of the matching sequence if any.
+    fn leader_key(&mut self, keycode: KeyCode, coord: (u8, u8)) -> CustomEvent<T> {
+        let mut leader = match self.leader.take() {
+            Some(leader) => leader,
+            None => return CustomEvent::NoEvent,
+        };
+        if leader.keys.push(keycode).is_err() {
+            return self.leader_failure(leader);
+        }
+        let keys = &leader.keys[..];
+        let sequences = leader.config.sequences;
+        if let Some((_, action)) = sequences.iter().find(|(seq, _)| *seq == keys) {
+            return self.do_action(action, coord, 0);
+        }
+        if sequences.iter().any(|(seq, _)| seq.starts_with(keys)) {
+            leader.timeout = leader.config.timeout;
+            self.leader = Some(leader);
+            return CustomEvent::NoEvent;
+        }
+        self.leader_failure(leader)
+    }
+    /// Ends the capture of the leader key, performing its failure
+    /// action.
+    fn leader_failure(&mut self, leader: LeaderState<T>) -> CustomEvent<T> {
+        let LeaderState { config, coord, .. } = leader;
+        push_release(&mut self.stacked, &mut self.overflow, coord);
+        self.do_action(&config.failure, coord, 0)
+    }
+    /// Updates the state depending on key code presses (one shots,
+    /// caps word...) when a key code is pressed.
+    fn keycode_pressed(&mut self, keycode: KeyCode, coord: (u8, u8)) {
+        if !keycode.is_modifier() {
+            let mods = self
+                .keycodes()
+                .fold(0, |mods, kc| mods | kc.as_modifier_bit());
+            self.last_keycode = Some((keycode, mods));
+            self.use_oneshots(coord);
+            self.tap_hold_tracker.since_key_press = 0;
+        }
+        self.update_caps_word(keycode);
+    }
+    /// Presses a key code with the given modifier bitfield, as
+    /// [`Action::MultipleKeyCodes`].
+    fn press_with_mods(&mut self, keycode: KeyCode, mods: u8, coord: (u8, u8)) {
+        use KeyCode::*;
+        let modifiers = [LCtrl, LShift, LAlt, LGui, RCtrl, RShift, RAlt, RGui];
+        for &m in modifiers.iter().filter(|m| mods & m.as_modifier_bit() != 0) {
+            self.keycode_pressed(m, coord);
+            let _ = self.states.push(NormalKey { coord, keycode: m });
+        }
+        self.keycode_pressed(keycode, coord);
+        let _ = self.states.push(NormalKey { coord, keycode });
+    }
+    /// Moves the overflowed events back to the stack, if there is
+    /// some room.
+    fn restack(&mut self) {
+        while !self.stacked.is_full() {
+            match self.overflow.pop_front() {
+                Some(s) => {
+                    let _ = self.stacked.push_back(s);
+                }
+                None => break,
+            }
+        }
+    }
     /// Register a key event.
     pub fn event(&mut self, event: Event) {
-        if let Some(stacked) = self.stacked.push_back(event.into()) {
+        self.restack();
+        let mut event = event.into();
+        if !self.overflow.is_empty() {
+            // The overflowed events are older, the event must stay
+            // after them.
+            match self.overflow.push_back(event) {
+                Ok(()) => return,
+                Err(e) => {
+                    event = self.overflow.pop_front().unwrap();
+                    let _ = self.overflow.push_back(e.element);
+                }
+            }
+        }
+        if let Some(stacked) = self.stacked.push_back(event) {
             self.waiting_into_hold();
             self.unstack(stacked);
         }
@@ -421,19 +1264,23 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
     fn press_as_action(&self, coord: (u8, u8), layer: usize) -> &'static Action<T> {
         use crate::action::Action::*;
         let action = self
-            .layers
-            .get(layer)
-            .and_then(|l| l.get(coord.0 as usize))
-            .and_then(|l| l.get(coord.1 as usize));
+            .keymap
+            .action(layer, coord.0 as usize, coord.1 as usize);
         match action {
             None => &NoOp,
-            Some(Trans) => {
-                if layer != self.default_layer {
+            Some(Trans) => match self.layer_mode {
+                LayerMode::Last if layer != self.default_layer => {
                     self.press_as_action(coord, self.default_layer)
-                } else {
-                    &NoOp
                 }
-            }
+                LayerMode::Stacked => {
+                    let below = (1u64 << layer.min(32)) - 1;
+                    match self.layer_state() & below as u32 {
+                        0 => &NoOp,
+                        lower => self.press_as_action(coord, highest_layer(lower)),
+                    }
+                }
+                _ => &NoOp,
+            },
             Some(action) => action,
         }
     }
@@ -453,18 +1300,25 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
                 tap,
                 config,
                 tap_hold_interval,
+                require_prior_idle,
+                retro_tap,
             }) => {
-                if *tap_hold_interval == 0
-                    || coord != self.tap_hold_tracker.coord
-                    || self.tap_hold_tracker.timeout == 0
+                let typing = self.tap_hold_tracker.since_key_press < *require_prior_idle;
+                if !typing
+                    && (*tap_hold_interval == 0
+                        || coord != self.tap_hold_tracker.coord
+                        || self.tap_hold_tracker.timeout == 0)
                 {
                     let waiting: WaitingState<T> = WaitingState {
                         coord,
                         timeout: *timeout,
                         delay,
-                        hold,
-                        tap,
-                        config: *config,
+                        config: WaitingConfig::HoldTap {
+                            hold,
+                            tap,
+                            config: *config,
+                            retro_tap: *retro_tap,
+                        },
                     };
                     self.waiting = Some(waiting);
                     self.tap_hold_tracker.timeout = *tap_hold_interval;
@@ -475,16 +1329,49 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
                 // Need to set tap_hold_tracker coord AFTER the checks.
                 self.tap_hold_tracker.coord = coord;
             }
+            TapDance(td) => {
+                self.tap_hold_tracker.coord = coord;
+                self.waiting = Some(WaitingState {
+                    coord,
+                    timeout: td.timeout,
+                    delay,
+                    config: WaitingConfig::TapDance(td),
+                });
+            }
             &KeyCode(keycode) => {
                 self.tap_hold_tracker.coord = coord;
+                if self.leader.is_some() && !keycode.is_modifier() {
+                    return self.leader_key(keycode, coord);
+                }
+                if let Some(config) = self.auto_shift {
+                    if config.keycodes.contains(&keycode) && !self.is_shortcut_mod_active() {
+                        self.waiting = Some(WaitingState {
+                            coord,
+                            timeout: config.timeout,
+                            delay,
+                            config: WaitingConfig::AutoShift(keycode),
+                        });
+                        return CustomEvent::NoEvent;
+                    }
+                }
+                self.keycode_pressed(keycode, coord);
                 let _ = self.states.push(NormalKey { coord, keycode });
             }
             &MultipleKeyCodes(v) => {
                 self.tap_hold_tracker.coord = coord;
                 for &keycode in *v {
+                    self.keycode_pressed(keycode, coord);
                     let _ = self.states.push(NormalKey { coord, keycode });
                 }
             }
+            &Sequence(events) => {
+                self.tap_hold_tracker.coord = coord;
+                let _ = self.sequences.push_back(SequenceState {
+                    events: SequenceEvents::Static { events, pos: 0 },
+                    delay: 0,
+                    tapped: None,
+                });
+            }
             &MultipleActions(v) => {
                 self.tap_hold_tracker.coord = coord;
                 let mut custom = CustomEvent::NoEvent;
@@ -497,35 +1384,321 @@ impl<const C: usize, const R: usize, const L: usize, T: 'static> Layout<C, R, L,
                 self.tap_hold_tracker.coord = coord;
                 let _ = self.states.push(LayerModifier { value, coord });
             }
+            &Consumer(code) => {
+                self.tap_hold_tracker.coord = coord;
+                let _ = self.states.push(State::Consumer { code, coord });
+            }
+            &System(code) => {
+                self.tap_hold_tracker.coord = coord;
+                let _ = self.states.push(State::System { code, coord });
+            }
+            &Mouse(key) => {
+                self.tap_hold_tracker.coord = coord;
+                let _ = self.states.push(State::Mouse { key, coord });
+            }
+            OneShot(OneShotAction { action, timeout }) => {
+                self.tap_hold_tracker.coord = coord;
+                if let Some(pos) = self.oneshots.iter().position(|os| os.coord == coord) {
+                    // Pressed again while waiting: cancel the one shot.
+                    self.oneshots.swap_remove(pos);
+                    return self.release(coord);
+                }
+                let custom = self.do_action(action, coord, delay);
+                let _ = self
+                    .oneshots
+                    .push(OneShotState::new(coord, *timeout, false));
+                return custom;
+            }
+            OneShotLayer(OneShotLayerAction { layer, timeout }) => {
+                self.tap_hold_tracker.coord = coord;
+                if let Some(pos) = self.oneshots.iter().position(|os| os.coord == coord) {
+                    // Pressed again while waiting: cancel the one shot.
+                    self.oneshots.swap_remove(pos);
+                    return self.release(coord);
+                }
+                let value = *layer;
+                let _ = self.states.push(LayerModifier { value, coord });
+                let _ = self.oneshots.push(OneShotState::new(coord, *timeout, true));
+            }
             DefaultLayer(value) => {
                 self.tap_hold_tracker.coord = coord;
                 self.set_default_layer(*value);
             }
-            Custom(value) => {
+            &ToggleLayer(value) => {
                 self.tap_hold_tracker.coord = coord;
-                if self.states.push(State::Custom { value, coord }).is_ok() {
-                    return CustomEvent::Press(value);
+                self.toggle_layer(value);
+            }
+            &ToLayer(value) => {
+                self.tap_hold_tracker.coord = coord;
+                self.toggled_layers.clear();
+                self.states.retain(|s| s.get_layer().is_none());
+                if value != self.default_layer {
+                    self.toggle_layer(value);
                 }
             }
-        }
-        CustomEvent::NoEvent
-    }
-
-    /// Obtain the index of the current active layer
+            LayerLock => {
+                self.tap_hold_tracker.coord = coord;
+                let value = self.current_layer();
+                if value != self.default_layer || self.toggled_layers.contains(&value) {
+                    self.toggle_layer(value);
+                }
+            }
+            CapsWord(config) => {
+                self.tap_hold_tracker.coord = coord;
+                self.caps_word = match self.caps_word {
+                    Some(_) => None,
+                    None => Some(CapsWordState::new(config)),
+                };
+            }
+            Leader(config) => {
+                self.tap_hold_tracker.coord = coord;
+                self.leader = match self.leader {
+                    Some(_) => None,
+                    None => Some(LeaderState::new(config, coord)),
+                };
+            }
+            ModMorph(ModMorphAction {
+                mods,
+                default,
+                morphed,
+            }) => {
+                let held = self
+                    .keycodes()
+                    .filter(|kc| mods.contains(kc))
+                    .fold(0, |acc, kc| acc | kc.as_modifier_bit());
+                if held == 0 {
+                    return self.do_action(default, coord, delay);
+                }
+                let _ = self.states.push(SuppressedMods { mods: held, coord });
+                return self.do_action(morphed, coord, delay);
+            }
+            Repeat => {
+                self.tap_hold_tracker.coord = coord;
+                if let Some((keycode, mods)) = self.last_keycode {
+                    self.press_with_mods(keycode, mods, coord);
+                }
+            }
+            &AltRepeat(pairs) => {
+                self.tap_hold_tracker.coord = coord;
+                if let Some((last, mods)) = self.last_keycode {
+                    if let Some(&(_, keycode)) = pairs.iter().find(|(kc, _)| *kc == last) {
+                        self.press_with_mods(keycode, mods, coord);
+                    }
+                }
+            }
+            &DynamicMacroRecord(slot) => {
+                self.tap_hold_tracker.coord = coord;
+                if self.recording.is_some() {
+                    self.record_dynamic_macro();
+                    self.recording = None;
+                } else if slot < self.dynamic_macros.len() {
+                    self.dynamic_macros[slot].clear();
+                    self.dynamic_macro_overflowed = false;
+                    self.recording = Some(RecordingState {
+                        slot,
+                        keycodes: self.all_keycodes().collect(),
+                        idle: 0,
+                    });
+                }
+            }
+            DynamicMacroStop => {
+                self.tap_hold_tracker.coord = coord;
+                if self.recording.is_some() {
+                    self.record_dynamic_macro();
+                    self.recording = None;
+                }
+            }
+            &DynamicMacroPlay(slot) => {
+                self.tap_hold_tracker.coord = coord;
+                let recorded = self.recording.as_ref().map(|rec| rec.slot);
+                if slot < self.dynamic_macros.len() && recorded != Some(slot) {
+                    let _ = self.sequences.push_back(SequenceState {
+                        events: SequenceEvents::DynamicMacro { slot, pos: 0 },
+                        delay: 0,
+                        tapped: None,
+                    });
+                }
+            }
+            Custom(value) => {
+                self.tap_hold_tracker.coord = coord;
+                if self.states.push(State::Custom { value, coord }).is_ok() {
+                    return CustomEvent::Press(value);
+                }
+            }
+        }
+        CustomEvent::NoEvent
+    }
+
+    /// Returns `true` if caps word is active (see [`Action::CapsWord`]).
+    pub fn is_caps_word_active(&self) -> bool {
+        self.caps_word.is_some()
+    }
+
+    /// Returns `true` if the key presses are captured after a leader
+    /// key (see [`Action::Leader`]).
+    pub fn is_leader_active(&self) -> bool {
+        self.leader.is_some()
+    }
+
+    /// Returns the keymap of the layout.
+    pub fn keymap(&self) -> &K {
+        &self.keymap
+    }
+
+    /// Replaces the keymap of the layout.
+    ///
+    /// The keys already pressed are not modified.
+    pub fn set_keymap(&mut self, keymap: K) {
+        self.keymap = keymap;
+    }
+
+    /// Returns the keymap of the layout, to be modified.
+    ///
+    /// The keys already pressed are not modified.
+    pub fn keymap_mut(&mut self) -> &mut K {
+        &mut self.keymap
+    }
+
+    /// Returns the slot of the dynamic macro being recorded, if any
+    /// (see [`Action::DynamicMacroRecord`]).
+    ///
+    /// The key code presses and releases are recorded, with a
+    /// maximum of 64 events in each of the 2 slots.
+    pub fn recording_dynamic_macro(&self) -> Option<usize> {
+        self.recording.as_ref().map(|rec| rec.slot)
+    }
+
+    /// Returns `true` if the recording of the last dynamic macro was
+    /// stopped because its buffer was full. The events recorded
+    /// before are kept.
+    pub fn dynamic_macro_overflowed(&self) -> bool {
+        self.dynamic_macro_overflowed
+    }
+
+    /// Obtain the index of the current active layer
     pub fn current_layer(&self) -> usize {
+        match (self.layer_mode, self.layer_state()) {
+            (LayerMode::Stacked, state) if state != 0 => highest_layer(state),
+            _ => self.layer_stack().next().unwrap_or(self.default_layer),
+        }
+    }
+
+    /// Returns the active layers as a bitmask: the bit `n` is set if
+    /// the layer `n` is active. Only the first 32 layers are
+    /// represented.
+    pub fn layer_state(&self) -> u32 {
+        self.layer_stack()
+            .filter(|&l| l < 32)
+            .fold(0, |state, l| state | 1 << l)
+    }
+
+    /// Iterates on the active layers, from the one defining the
+    /// current layer to the default layer.
+    ///
+    /// The active layers are the conditional layers (see
+    /// [`Layout::set_conditional_layers`]), the layers activated by a
+    /// held key, the toggled layers and the default layer.
+    pub fn active_layers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.layer_stack()
+            .enumerate()
+            .filter(move |&(i, layer)| self.layer_stack().position(|l| l == layer) == Some(i))
+            .map(|(_, layer)| layer)
+    }
+
+    /// Returns `true` if the given layer is active (see
+    /// [`Layout::active_layers`]).
+    pub fn is_layer_active(&self, layer: usize) -> bool {
+        self.layer_stack().any(|l| l == layer)
+    }
+
+    /// Returns the toggled layers, in the order they were toggled.
+    pub fn toggled_layers(&self) -> &[usize] {
+        &self.toggled_layers
+    }
+
+    /// Toggles a layer (see [`Action::ToggleLayer`]).
+    pub fn toggle_layer(&mut self, value: usize) {
+        if let Some(pos) = self.toggled_layers.iter().position(|&l| l == value) {
+            self.toggled_layers.remove(pos);
+        } else if value < L {
+            let _ = self.toggled_layers.push(value);
+        }
+    }
+
+    fn layer_stack(&self) -> impl Iterator<Item = usize> + '_ {
+        self.conditional_layers
+            .iter()
+            .rev()
+            .filter(move |c| {
+                c.layers
+                    .iter()
+                    .all(|&layer| self.base_layer_stack().any(|l| l == layer))
+            })
+            .map(|c| c.layer)
+            .chain(self.base_layer_stack())
+    }
+
+    /// The layer stack without the conditional layers.
+    fn base_layer_stack(&self) -> impl Iterator<Item = usize> + '_ {
         self.states
             .iter()
             .rev()
-            .find_map(State::get_layer)
-            .unwrap_or(self.default_layer)
+            .filter_map(State::get_layer)
+            .chain(self.toggled_layers.iter().rev().copied())
+            .chain(core::iter::once(self.default_layer))
+    }
+
+    /// Returns the default layer of the layout.
+    pub fn default_layer(&self) -> usize {
+        self.default_layer
     }
 
     /// Sets the default layer for the layout
     pub fn set_default_layer(&mut self, value: usize) {
-        if value < self.layers.len() {
+        if value < L {
             self.default_layer = value
         }
     }
+
+    /// Sets the auto shift configuration of the layout, `None`
+    /// deactivating auto shift.
+    ///
+    /// The auto shifted key codes are the ones of [`Action::KeyCode`].
+    /// When such a key is pressed, the key code is sent when the key
+    /// is released, or shifted if the key is held more than the
+    /// timeout of the configuration.
+    pub fn set_auto_shift(&mut self, config: Option<&'static AutoShiftConfig>) {
+        self.auto_shift = config;
+    }
+
+    /// Sets the mouse keys configuration of the layout, by default
+    /// [`MouseConfig::DEFAULT`].
+    pub fn set_mouse_config(&mut self, config: &'static MouseConfig) {
+        self.mouse_config = config;
+    }
+
+    /// Sets how the active layers define the action of a key (see
+    /// [`LayerMode`]).
+    pub fn set_layer_mode(&mut self, layer_mode: LayerMode) {
+        self.layer_mode = layer_mode;
+    }
+
+    /// Sets the conditional layers of the layout.
+    ///
+    /// After computing the active layers, the layer of each
+    /// [`ConditionalLayer`] whose layers are all active is activated,
+    /// and takes precedence over the other active layers. If several
+    /// conditional layers are activated, the last one defines the
+    /// current layer. Conditional layers do not activate other
+    /// conditional layers.
+    pub fn set_conditional_layers(&mut self, conditional_layers: &'static [ConditionalLayer]) {
+        self.conditional_layers = conditional_layers;
+    }
+}
+
+/// Returns the highest layer of a non empty layer bitmask.
+fn highest_layer(state: u32) -> usize {
+    31 - state.leading_zeros() as usize
 }
 
 #[cfg(test)]
@@ -533,8 +1706,12 @@ mod test {
     extern crate std;
     use super::{Event::*, Layout, *};
     use crate::action::Action::*;
-    use crate::action::HoldTapConfig;
+    use crate::action::SequenceEvent;
     use crate::action::{k, l, m};
+    use crate::action::{
+        CapsWordConfig, HoldTapConfig, LeaderConfig, ModMorphAction, OneShotAction,
+        OneShotLayerAction, TapDanceAction,
+    };
     use crate::key_code::KeyCode;
     use crate::key_code::KeyCode::*;
     use std::collections::BTreeSet;
@@ -556,6 +1733,8 @@ mod test {
                     tap: k(Space),
                     config: HoldTapConfig::Default,
                     tap_hold_interval: 0,
+                    require_prior_idle: 0,
+                    retro_tap: false,
                 }),
                 HoldTap(&HoldTapAction {
                     timeout: 200,
@@ -563,6 +1742,8 @@ mod test {
                     tap: k(Enter),
                     config: HoldTapConfig::Default,
                     tap_hold_interval: 0,
+                    require_prior_idle: 0,
+                    retro_tap: false,
                 }),
             ]],
             [[Trans, m(&[LCtrl, Enter].as_slice())]],
@@ -605,6 +1786,8 @@ mod test {
                 tap: k(Space),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             HoldTap(&HoldTapAction {
                 timeout: 20,
@@ -612,6 +1795,8 @@ mod test {
                 tap: k(Enter),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
         ]]];
         let mut layout = Layout::new(&LAYERS);
@@ -652,6 +1837,8 @@ mod test {
                 tap: k(Space),
                 config: HoldTapConfig::HoldOnOtherKeyPress,
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             k(Enter),
         ]]];
@@ -709,6 +1896,8 @@ mod test {
                 tap: k(Space),
                 config: HoldTapConfig::PermissiveHold,
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             k(Enter),
         ]]];
@@ -868,6 +2057,8 @@ mod test {
                 tap: k(Kb0),
                 config: HoldTapConfig::Custom(always_tap),
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             HoldTap(&HoldTapAction {
                 timeout: 200,
@@ -875,6 +2066,8 @@ mod test {
                 tap: k(Kb2),
                 config: HoldTapConfig::Custom(always_hold),
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             HoldTap(&HoldTapAction {
                 timeout: 200,
@@ -882,6 +2075,8 @@ mod test {
                 tap: k(Kb4),
                 config: HoldTapConfig::Custom(always_nop),
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             HoldTap(&HoldTapAction {
                 timeout: 200,
@@ -889,6 +2084,8 @@ mod test {
                 tap: k(Kb6),
                 config: HoldTapConfig::Custom(always_none),
                 tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
         ]]];
         let mut layout = Layout::new(&LAYERS);
@@ -961,6 +2158,8 @@ mod test {
                 tap: k(Space),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 200,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             k(Enter),
         ]]];
@@ -1015,6 +2214,8 @@ mod test {
                 tap: k(Space),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 200,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             k(Enter),
             HoldTap(&HoldTapAction {
@@ -1023,6 +2224,8 @@ mod test {
                 tap: k(Enter),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 200,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
         ]]];
         let mut layout = Layout::new(&LAYERS);
@@ -1135,6 +2338,8 @@ mod test {
             tap: k(Space),
             config: HoldTapConfig::Default,
             tap_hold_interval: 200,
+            require_prior_idle: 0,
+            retro_tap: false,
         })]]];
         let mut layout = Layout::new(&LAYERS);
 
@@ -1177,6 +2382,8 @@ mod test {
                 tap: k(Space),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 200,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
             HoldTap(&HoldTapAction {
                 timeout: 200,
@@ -1184,6 +2391,8 @@ mod test {
                 tap: k(Enter),
                 config: HoldTapConfig::Default,
                 tap_hold_interval: 200,
+                require_prior_idle: 0,
+                retro_tap: false,
             }),
         ]]];
         let mut layout = Layout::new(&LAYERS);
@@ -1215,4 +2424,1348 @@ mod test {
             assert_keys(&[Enter], layout.keycodes());
         }
     }
+
+    #[test]
+    fn tap_dance() {
+        static LAYERS: Layers<2, 1, 1> = [[[
+            TapDance(&TapDanceAction {
+                timeout: 100,
+                actions: &[k(A), k(B), k(C)],
+                hold: Some(k(LCtrl)),
+            }),
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // single tap
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        for _ in 0..99 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // double tap
+        for _ in 0..2 {
+            layout.event(Press(0, 0));
+            for _ in 0..50 {
+                assert_eq!(CustomEvent::NoEvent, layout.tick());
+            }
+            layout.event(Release(0, 0));
+            for _ in 0..50 {
+                assert_eq!(CustomEvent::NoEvent, layout.tick());
+                assert_keys(&[], layout.keycodes());
+            }
+        }
+        for _ in 0..49 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // more taps than actions, interrupted by another key
+        for _ in 0..4 {
+            layout.event(Press(0, 0));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            layout.event(Release(0, 0));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[C], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // tap and hold
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 0));
+        for _ in 0..99 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, Enter], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn tap_dance_interrupted() {
+        static LAYERS: Layers<2, 1, 1> = [[[
+            TapDance(&TapDanceAction {
+                timeout: 100,
+                actions: &[k(A), k(B), k(C)],
+                hold: Some(k(LCtrl)),
+            }),
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // released when another key is pressed: the tap action
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // held when another key is pressed: the hold action
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, Enter], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn tap_dance_more_taps_than_actions() {
+        static LAYERS: Layers<1, 1, 1> = [[[TapDance(&TapDanceAction {
+            timeout: 100,
+            actions: &[k(A), k(B)],
+            hold: None,
+        })]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        for _ in 0..5 {
+            layout.event(Press(0, 0));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            layout.event(Release(0, 0));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        for _ in 0..98 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        // the last action is performed
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn tap_dance_held_at_timeout() {
+        static LAYERS: Layers<2, 1, 1> = [[[
+            TapDance(&TapDanceAction {
+                timeout: 100,
+                actions: &[k(A), k(B)],
+                hold: Some(k(LCtrl)),
+            }),
+            TapDance(&TapDanceAction {
+                timeout: 100,
+                actions: &[k(A), k(B)],
+                hold: None,
+            }),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // the hold action is held until the key is released
+        layout.event(Press(0, 0));
+        for _ in 0..100 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[LCtrl], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // without hold action, the action of the taps is held
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        for _ in 0..99 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[B], layout.keycodes());
+        }
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn sequence() {
+        static LAYERS: Layers<2, 1, 1> = [[[
+            Sequence(
+                &[
+                    SequenceEvent::Press(LShift),
+                    SequenceEvent::Tap(A),
+                    SequenceEvent::Release(LShift),
+                    SequenceEvent::Delay(3),
+                    SequenceEvent::Tap(B),
+                    SequenceEvent::Tap(B),
+                    SequenceEvent::Press(C),
+                ]
+                .as_slice(),
+            ),
+            k(LCtrl),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl], layout.keycodes());
+        // the physical keys are independent of the sequence
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, LShift], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, LShift, A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        for _ in 0..2 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[B], layout.keycodes());
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[C], layout.keycodes());
+        // end of the sequence, the keys are released
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn one_shot() {
+        static LAYERS: Layers<3, 1, 1> = [[[
+            OneShot(&OneShotAction {
+                action: k(LShift),
+                timeout: 100,
+            }),
+            k(A),
+            OneShot(&OneShotAction {
+                action: k(LCtrl),
+                timeout: 100,
+            }),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // tap the one shot, the modifier is kept after the release
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+
+        // stacking with another one shot
+        layout.event(Press(0, 2));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl], layout.keycodes());
+
+        // the next key press uses the one shots
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl, A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // held, the one shot is a classic key
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, A], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // timeout
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        for _ in 0..100 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[LShift], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // tapping again cancels
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn one_shot_release_on_full_stack() {
+        static LAYERS: Layers<4, 1, 1> = [[[
+            OneShot(&OneShotAction {
+                action: k(LShift),
+                timeout: 10,
+            }),
+            k(A),
+            HoldTap(&HoldTapAction::new(200, k(LCtrl), k(C))),
+            k(B),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 2));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[LShift], layout.keycodes());
+
+        // the stack is full while the hold tap is waiting
+        for i in 0..15 {
+            layout.event(if i % 2 == 0 {
+                Press(0, 3)
+            } else {
+                Release(0, 3)
+            });
+        }
+        layout.event(Press(0, 1));
+
+        // the one shot timeout releases it, the last event is kept
+        for _ in 0..250 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[LCtrl, B, A], layout.keycodes());
+    }
+
+    #[test]
+    fn one_shot_layer() {
+        static LAYERS: Layers<3, 1, 2> = [
+            [[
+                OneShotLayer(&OneShotLayerAction {
+                    layer: 1,
+                    timeout: 100,
+                }),
+                k(A),
+                OneShot(&OneShotAction {
+                    action: k(LShift),
+                    timeout: 100,
+                }),
+            ]],
+            [[Trans, k(B), Trans]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        // tap the one shot layer, the layer is active for the next key press
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // combined with a one shot modifier
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 2));
+        layout.event(Release(0, 2));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(1, layout.current_layer());
+        assert_keys(&[LShift], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, B], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(0, layout.current_layer());
+
+        // held, the one shot layer is a momentary layer
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        for _ in 0..2 {
+            layout.event(Press(0, 1));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[B], layout.keycodes());
+            layout.event(Release(0, 1));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_eq!(1, layout.current_layer());
+        }
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+
+        // timeout
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        for _ in 0..101 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_eq!(1, layout.current_layer());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+    }
+
+    #[test]
+    fn toggle_layers() {
+        static LAYERS: Layers<4, 1, 4> = [
+            [[ToggleLayer(1), ToggleLayer(2), l(3), ToLayer(3)]],
+            [[Trans, Trans, Trans, Trans]],
+            [[Trans, Trans, Trans, Trans]],
+            [[ToLayer(0), Trans, Trans, Trans]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+        let active =
+            |layout: &Layout<4, 1, 4>| layout.active_layers().collect::<std::vec::Vec<_>>();
+
+        // toggle L1 then L2
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(2, layout.current_layer());
+        assert_eq!(&[1, 2], layout.toggled_layers());
+        assert_eq!(std::vec![2, 1, 0], active(&layout));
+
+        // a held layer has precedence
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(3, layout.current_layer());
+        assert_eq!(std::vec![3, 2, 1, 0], active(&layout));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(2, layout.current_layer());
+
+        // untoggle L2
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        assert!(!layout.is_layer_active(2));
+
+        // switch to L3
+        layout.event(Press(0, 3));
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(3, layout.current_layer());
+        assert_eq!(std::vec![3, 0], active(&layout));
+
+        // and back to L0
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+        assert_eq!(std::vec![0], active(&layout));
+    }
+
+    #[test]
+    fn layer_lock() {
+        static LAYERS: Layers<2, 1, 2> = [[[l(1), k(A)]], [[Trans, LayerLock]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // lock L1 while holding it
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(1, layout.current_layer());
+        assert_keys(&[], layout.keycodes());
+
+        // unlock it
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(0, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn dynamic_macro_full_states() {
+        static CONFIG: CapsWordConfig = CapsWordConfig {
+            timeout: 100,
+            shifted: &[],
+            continuing: &[],
+        };
+        static LAYERS: Layers<66, 1, 1> = [[{
+            let mut keys = [k(A); 66];
+            keys[0] = CapsWord(&CONFIG);
+            keys[1] = DynamicMacroRecord(0);
+            keys
+        }]];
+        let mut layout = Layout::new(&LAYERS);
+        for j in 0..2 {
+            layout.event(Press(0, j));
+            layout.event(Release(0, j));
+        }
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert!(layout.is_caps_word_active());
+        assert_eq!(Some(0), layout.recording_dynamic_macro());
+
+        // 64 key codes and the caps word shift are recorded
+        for j in 2..66 {
+            layout.event(Press(0, j));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(65, layout.keycodes().count());
+    }
+
+    #[test]
+    fn caps_word() {
+        static CONFIG: CapsWordConfig = CapsWordConfig {
+            timeout: 100,
+            shifted: &[Minus],
+            continuing: &[Kb1],
+        };
+        static LAYERS: Layers<5, 1, 1> = [[[CapsWord(&CONFIG), k(A), k(Minus), k(Kb1), k(Space)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(layout.is_caps_word_active());
+
+        // letters and shifted key codes are shifted
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A, LShift], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Minus, LShift], layout.keycodes());
+        layout.event(Release(0, 2));
+
+        // continuing key codes are not shifted
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Kb1], layout.keycodes());
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(layout.is_caps_word_active());
+
+        // in a rollover, only the last pressed key is shifted
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A, LShift], layout.keycodes());
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A, Kb1], layout.keycodes());
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A, LShift], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Kb1], layout.keycodes());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Kb1, A, LShift], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert!(layout.is_caps_word_active());
+
+        // other key codes end the word
+        layout.event(Press(0, 4));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        assert!(!layout.is_caps_word_active());
+        layout.event(Release(0, 4));
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // timeout
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(layout.is_caps_word_active());
+        for _ in 0..99 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert!(!layout.is_caps_word_active());
+    }
+
+    #[test]
+    fn leader() {
+        static CONFIG: LeaderConfig<u8> = LeaderConfig {
+            timeout: 100,
+            sequences: &[(&[A, B], k(Enter)), (&[C], Custom(1))],
+            failure: Custom(0),
+        };
+        static LAYERS: Layers<4, 1, 1, u8> = [[[Leader(&CONFIG), k(A), k(B), k(C)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // matching sequence, the action is released with the last key
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 2));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert!(layout.is_leader_active());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Enter], layout.keycodes());
+        assert!(!layout.is_leader_active());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // custom action
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 3));
+        layout.event(Release(0, 3));
+        for _ in 0..2 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(CustomEvent::Press(&1), layout.tick());
+        assert_eq!(CustomEvent::Release(&1), layout.tick());
+
+        // failure
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(CustomEvent::Press(&0), layout.tick());
+        assert_eq!(CustomEvent::Release(&0), layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // timeout
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        for _ in 0..102 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        // the event of the timeout tick is not delayed
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::Press(&0), layout.tick());
+        assert_keys(&[C], layout.keycodes());
+        assert_eq!(CustomEvent::Release(&0), layout.tick());
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // cancel
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert!(!layout.is_leader_active());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn mod_morph() {
+        static MOD_MORPH: ModMorphAction<core::convert::Infallible> = ModMorphAction {
+            mods: &[LShift, RShift],
+            default: k(BSpace),
+            morphed: k(Delete),
+        };
+        static LAYERS: Layers<3, 1, 1> = [[[k(LShift), ModMorph(&MOD_MORPH), k(LCtrl)]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[BSpace], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // the held modifier is removed while the key is held
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 2));
+        layout.event(Press(0, 1));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[LCtrl, Delete], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, LCtrl], layout.keycodes());
+    }
+
+    #[test]
+    fn conditional_layers() {
+        static LAYERS: Layers<3, 1, 4> = [
+            [[l(1), l(2), k(A)]],
+            [[Trans, Trans, k(B)]],
+            [[Trans, Trans, k(C)]],
+            [[Trans, Trans, k(D)]],
+        ];
+        static CONDITIONAL_LAYERS: [ConditionalLayer; 1] = [ConditionalLayer {
+            layers: &[1, 2],
+            layer: 3,
+        }];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_conditional_layers(&CONDITIONAL_LAYERS);
+
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(1, layout.current_layer());
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(3, layout.current_layer());
+        assert_eq!(
+            std::vec![3, 2, 1, 0],
+            layout.active_layers().collect::<std::vec::Vec<_>>()
+        );
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[D], layout.keycodes());
+        layout.event(Release(0, 2));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(2, layout.current_layer());
+    }
+
+    #[test]
+    fn stacked_layers() {
+        static LAYERS: Layers<4, 1, 3> = [
+            [[k(A), k(B), l(2), l(1)]],
+            [[k(C), Trans, Trans, Trans]],
+            [[Trans, Trans, Trans, Trans]],
+        ];
+        let mut layout = Layout::new(&LAYERS);
+
+        layout.event(Press(0, 2));
+        layout.event(Press(0, 3));
+        layout.event(Press(0, 0));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(1, layout.current_layer());
+        assert_eq!(0b111, layout.layer_state());
+        assert_keys(&[C], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // the highest layer is the current one, falling through the
+        // active layers
+        layout.set_layer_mode(LayerMode::Stacked);
+        assert_eq!(2, layout.current_layer());
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[C, B], layout.keycodes());
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 3));
+        layout.event(Press(0, 0));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(0b101, layout.layer_state());
+        assert_keys(&[A], layout.keycodes());
+    }
+
+    #[test]
+    fn require_prior_idle() {
+        static LAYERS: Layers<2, 1, 1> = [[[
+            HoldTap(&HoldTapAction {
+                timeout: 200,
+                hold: k(LAlt),
+                tap: k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                require_prior_idle: 100,
+                retro_tap: false,
+            }),
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // Pressed just after another key: tap
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // Pressed after an idle period: hold
+        for _ in 0..100 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+    }
+
+    #[test]
+    fn retro_tap() {
+        static LAYERS: Layers<2, 1, 1> = [[[
+            HoldTap(&HoldTapAction {
+                timeout: 200,
+                hold: k(LAlt),
+                tap: k(Space),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: true,
+            }),
+            k(Enter),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // Held past the timeout without other key press
+        layout.event(Press(0, 0));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[Space], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // Another key pressed while held: no retro tap
+        layout.event(Press(0, 0));
+        for _ in 0..201 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[LAlt], layout.keycodes());
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt, Enter], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LAlt], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn auto_shift() {
+        static CONFIG: AutoShiftConfig = AutoShiftConfig {
+            timeout: 100,
+            keycodes: &[A],
+            repeat: 50,
+        };
+        static LAYERS: Layers<3, 1, 1> = [[[k(A), k(B), k(LCtrl)]]];
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_auto_shift(Some(&CONFIG));
+
+        // tap
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // other key codes are not auto shifted
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        // hold: the shifted key code is tapped, then repeated
+        layout.event(Press(0, 0));
+        for _ in 0..100 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, A], layout.keycodes());
+        for _ in 0..49 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, A], layout.keycodes());
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // no auto shift with the other modifiers
+        layout.event(Press(0, 2));
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LCtrl, A], layout.keycodes());
+        for _ in 0..150 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[LCtrl, A], layout.keycodes());
+        }
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+    }
+
+    #[test]
+    fn repeat() {
+        static LAYERS: Layers<4, 1, 1> = [[[
+            k(LShift),
+            k(Up),
+            Repeat,
+            AltRepeat(&[(Up, Down), (Left, Right)].as_slice()),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        // nothing to repeat
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 0));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[], layout.keycodes());
+
+        // repeated with the modifiers
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, Up], layout.keycodes());
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // counterpart
+        layout.event(Press(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, Down], layout.keycodes());
+        layout.event(Release(0, 3));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // the counterpart is now the last key code
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[LShift, Down], layout.keycodes());
+    }
+
+    #[test]
+    fn dynamic_macro() {
+        static LAYERS: Layers<5, 1, 1> = [[[
+            DynamicMacroRecord(0),
+            DynamicMacroPlay(0),
+            DynamicMacroRecord(1),
+            k(A),
+            k(B),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(Some(0), layout.recording_dynamic_macro());
+        layout.event(Press(0, 3));
+        layout.event(Press(0, 4));
+        layout.event(Release(0, 3));
+        layout.event(Release(0, 4));
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        for _ in 0..6 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(None, layout.recording_dynamic_macro());
+        assert!(!layout.dynamic_macro_overflowed());
+
+        // playing the recorded key codes
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A, B], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // the time between the changes is recorded
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        for _ in 0..10 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Press(0, 3));
+        for _ in 0..5 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        layout.event(Release(0, 3));
+        layout.event(Press(0, 0));
+        layout.event(Release(0, 0));
+        for _ in 0..4 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(None, layout.recording_dynamic_macro());
+        layout.event(Press(0, 1));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        for _ in 0..5 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert_keys(&[A], layout.keycodes());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+
+        // overflow
+        layout.event(Press(0, 2));
+        layout.event(Release(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(Some(1), layout.recording_dynamic_macro());
+        for _ in 0..33 {
+            layout.event(Press(0, 3));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            layout.event(Release(0, 3));
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(None, layout.recording_dynamic_macro());
+        assert!(layout.dynamic_macro_overflowed());
+    }
+
+    #[test]
+    fn runtime_keymap() {
+        static LAYERS: Layers<2, 1, 2> = [[[l(1), k(A)]], [[Trans, Trans]]];
+        let mut layout = Layout::new_runtime(&LAYERS);
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[A], layout.keycodes());
+
+        // the pressed key is not modified
+        layout.set_action(0, 0, 1, &KeyCode(B));
+        layout.set_action(1, 0, 1, &KeyCode(C));
+        layout.set_action(2, 0, 1, &KeyCode(D));
+        assert_keys(&[A], layout.keycodes());
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[B], layout.keycodes());
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[C], layout.keycodes());
+        assert_eq!(Some(&k(C)), layout.keymap().action(1, 0, 1));
+
+        // setting a key code computed at runtime
+        let keycodes = [E, F];
+        layout.set_keycode(0, 0, 1, keycodes[1]);
+        layout.event(Release(0, 0));
+        layout.event(Release(0, 1));
+        layout.event(Press(0, 1));
+        for _ in 0..3 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[F], layout.keycodes());
+    }
+
+    #[test]
+    fn consumer_codes() {
+        use crate::consumer::ConsumerCode::{self, *};
+        static LAYERS: Layers<3, 1, 1> = [[[
+            Consumer(ConsumerCode::PlayPause),
+            k(MediaVolUp),
+            MultipleKeyCodes(&[LCtrl, MediaMute].as_slice()),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        let consumer =
+            |layout: &Layout<3, 1, 1>| layout.consumer_codes().collect::<std::vec::Vec<_>>();
+
+        layout.event(Press(0, 0));
+        layout.event(Press(0, 1));
+        for _ in 0..2 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(
+            std::vec![ConsumerCode::PlayPause, VolumeUp],
+            consumer(&layout)
+        );
+
+        layout.event(Release(0, 0));
+        layout.event(Press(0, 2));
+        for _ in 0..2 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert_keys(&[LCtrl], layout.keycodes());
+        assert_eq!(std::vec![VolumeUp, Mute], consumer(&layout));
+
+        layout.event(Release(0, 1));
+        layout.event(Release(0, 2));
+        for _ in 0..2 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+        }
+        assert!(consumer(&layout).is_empty());
+    }
+
+    #[test]
+    fn system_codes() {
+        use crate::system::SystemCode;
+        static LAYERS: Layers<2, 1, 1> = [[[
+            System(SystemCode::Sleep),
+            HoldTap(&HoldTapAction {
+                timeout: 200,
+                hold: System(SystemCode::PowerDown),
+                tap: System(SystemCode::WakeUp),
+                config: HoldTapConfig::Default,
+                tap_hold_interval: 0,
+                require_prior_idle: 0,
+                retro_tap: false,
+            }),
+        ]]];
+        let mut layout = Layout::new(&LAYERS);
+        let system = |layout: &Layout<2, 1, 1>| layout.system_codes().collect::<std::vec::Vec<_>>();
+
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_keys(&[], layout.keycodes());
+        assert_eq!(std::vec![SystemCode::Sleep], system(&layout));
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(system(&layout).is_empty());
+
+        layout.event(Press(0, 1));
+        for _ in 0..200 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert!(system(&layout).is_empty());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(std::vec![SystemCode::PowerDown], system(&layout));
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(system(&layout).is_empty());
+    }
+
+    #[test]
+    fn mouse_keys() {
+        use crate::mouse::*;
+        static LAYERS: Layers<3, 1, 1> = [[[
+            Mouse(MouseKey::Move(MouseDirection::Right)),
+            Mouse(MouseKey::Button(MouseButton::Left)),
+            Mouse(MouseKey::Wheel(MouseDirection::Down)),
+        ]]];
+        static CONFIG: MouseConfig = MouseConfig {
+            interval: 4,
+            delay: 0,
+            time_to_max: 8,
+            initial_speed: 1,
+            max_speed: 5,
+            curve: MouseCurve::Linear,
+            wheel_interval: 10,
+            wheel_speed: 1,
+        };
+        let mut layout = Layout::new(&LAYERS);
+        layout.set_mouse_config(&CONFIG);
+        let movement = |layout: &Layout<3, 1, 1>| layout.mouse_report().movement();
+
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(layout.mouse_report().is_still());
+        // the cursor moves on the press
+        layout.event(Press(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(movement(&layout), (1, 0));
+        assert_keys(&[], layout.keycodes());
+        // and then every 4 ticks, accelerating
+        let mut moves = std::vec::Vec::new();
+        for _ in 0..12 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            moves.push(movement(&layout).0);
+            assert_eq!(0, movement(&layout).1);
+        }
+        assert_eq!(moves, [0, 0, 0, 3, 0, 0, 0, 5, 0, 0, 0, 5]);
+
+        layout.event(Press(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(layout.mouse_report().buttons(), MouseButton::Left as u8);
+        layout.event(Release(0, 0));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert!(layout.mouse_report().is_still());
+        assert_eq!(layout.mouse_report().buttons(), MouseButton::Left as u8);
+        layout.event(Release(0, 1));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(layout.mouse_report(), MouseReport::default());
+
+        // scrolling down moves the wheel down
+        layout.event(Press(0, 2));
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(layout.mouse_report().wheel(), (-1, 0));
+        for _ in 0..9 {
+            assert_eq!(CustomEvent::NoEvent, layout.tick());
+            assert!(layout.mouse_report().is_still());
+        }
+        assert_eq!(CustomEvent::NoEvent, layout.tick());
+        assert_eq!(layout.mouse_report().wheel(), (-1, 0));
+    }
 }
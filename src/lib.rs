@@ -20,12 +20,19 @@ use usb_device::prelude::*;
 
 pub mod action;
 pub mod chording;
+pub mod consumer;
 pub mod debounce;
+pub mod encoding;
 pub mod hid;
 pub mod key_code;
 pub mod keyboard;
+pub mod keymap;
 pub mod layout;
 pub mod matrix;
+pub mod mouse;
+pub mod storage;
+pub mod system;
+pub mod via;
 
 /// A handly shortcut for the keyberon USB class type.
 pub type Class<'a, B, L> = hid::HidClass<'a, B, keyboard::Keyboard<L>>;
@@ -47,6 +54,16 @@ where
     hid::HidClass::new(keyboard::Keyboard::new(leds), bus)
 }
 
+/// Constructor for `Class`, using the N-key rollover report (see
+/// [`keyboard::Keyboard::new_nkro`]).
+pub fn new_nkro_class<B, L>(bus: &UsbBusAllocator<B>, leds: L) -> Class<'_, B, L>
+where
+    B: usb_device::bus::UsbBus,
+    L: keyboard::Leds,
+{
+    hid::HidClass::new(keyboard::Keyboard::new_nkro(leds), bus)
+}
+
 /// Constructor for a keyberon USB device.
 pub fn new_device<B>(bus: &UsbBusAllocator<B>) -> usb_device::device::UsbDevice<'_, B>
 where
@@ -0,0 +1,456 @@
+//! Mouse keys and mouse HID device implementation.
+//!
+//! The [`Action::Mouse`](crate::action::Action::Mouse) actions
+//! emulate a mouse: they press the mouse buttons, move the cursor
+//! and scroll the wheel. The cursor accelerates while the movement
+//! keys are held, following a [`MouseConfig`] (see
+//! [`Layout::set_mouse_config`](crate::layout::Layout::set_mouse_config)).
+//!
+//! The [`Layout`](crate::layout::Layout) gives the mouse report with
+//! [`Layout::mouse_report`](crate::layout::Layout::mouse_report),
+//! that must be sent with a [`Mouse`] HID device alongside the
+//! [`Keyboard`](crate::keyboard::Keyboard).
+
+use crate::hid::{self, HidDevice, Protocol, ProtocolMode, ReportType, Subclass};
+
+/// A mouse button.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MouseButton {
+    /// The left button.
+    Left = 0x01,
+    /// The right button.
+    Right = 0x02,
+    /// The middle button.
+    Middle = 0x04,
+    /// The back button.
+    Back = 0x08,
+    /// The forward button.
+    Forward = 0x10,
+}
+
+/// A direction of a mouse movement or of the mouse wheel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MouseDirection {
+    /// Up.
+    Up,
+    /// Down.
+    Down,
+    /// Left.
+    Left,
+    /// Right.
+    Right,
+}
+
+impl MouseDirection {
+    /// Returns the unit vector of the direction, `y` increasing
+    /// downward.
+    fn vector(self) -> (i8, i8) {
+        match self {
+            MouseDirection::Up => (0, -1),
+            MouseDirection::Down => (0, 1),
+            MouseDirection::Left => (-1, 0),
+            MouseDirection::Right => (1, 0),
+        }
+    }
+}
+
+/// A mouse key.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MouseKey {
+    /// Press a mouse button.
+    Button(MouseButton),
+    /// Move the cursor in the given direction.
+    Move(MouseDirection),
+    /// Scroll the wheel in the given direction, left and right
+    /// scrolling horizontally.
+    Wheel(MouseDirection),
+}
+
+/// The acceleration curve of the mouse cursor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MouseCurve {
+    /// The speed increases linearly with time.
+    Linear,
+    /// The speed increases with the square of the time, allowing
+    /// precise movements before accelerating.
+    Quadratic,
+}
+
+/// The configuration of the mouse keys.
+///
+/// When a movement key is pressed, the cursor moves of
+/// `initial_speed` every `interval` ticks. After `delay` ticks, the
+/// speed increases following `curve` to reach `max_speed` after
+/// `time_to_max` more ticks.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MouseConfig {
+    /// The number of ticks between two movements of the cursor.
+    pub interval: u16,
+    /// The number of ticks before the cursor accelerates.
+    pub delay: u16,
+    /// The number of ticks to accelerate from `initial_speed` to
+    /// `max_speed`.
+    pub time_to_max: u16,
+    /// The initial speed, in pixels per movement.
+    pub initial_speed: u8,
+    /// The maximum speed, in pixels per movement.
+    pub max_speed: u8,
+    /// The acceleration curve.
+    pub curve: MouseCurve,
+    /// The number of ticks between two wheel scrolls.
+    pub wheel_interval: u16,
+    /// The number of wheel steps of a scroll.
+    pub wheel_speed: u8,
+}
+
+impl MouseConfig {
+    /// The default configuration: the cursor moves every 16 ticks,
+    /// and accelerates from 1 to 20 pixels during 1 second after
+    /// 100 ticks. The wheel scrolls every 80 ticks.
+    pub const DEFAULT: MouseConfig = MouseConfig {
+        interval: 16,
+        delay: 100,
+        time_to_max: 1000,
+        initial_speed: 1,
+        max_speed: 20,
+        curve: MouseCurve::Quadratic,
+        wheel_interval: 80,
+        wheel_speed: 1,
+    };
+
+    /// Returns the speed of the cursor after being moved during the
+    /// given number of ticks.
+    pub fn speed(&self, ticks: u16) -> i8 {
+        let (initial, max) = (self.initial_speed as u64, self.max_speed as u64);
+        let t = ticks.saturating_sub(self.delay) as u64;
+        let time_to_max = self.time_to_max as u64;
+        let speed = if t >= time_to_max || max <= initial {
+            max
+        } else {
+            match self.curve {
+                MouseCurve::Linear => initial + (max - initial) * t / time_to_max,
+                MouseCurve::Quadratic => {
+                    initial + (max - initial) * t * t / (time_to_max * time_to_max)
+                }
+            }
+        };
+        speed.min(i8::MAX as u64) as i8
+    }
+}
+
+impl Default for MouseConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The state of the mouse keys, used by the layout.
+#[derive(Debug, Default)]
+pub(crate) struct MouseState {
+    /// The number of ticks the cursor has been moved, giving its
+    /// speed.
+    move_ticks: u16,
+    /// The ticks since the last movement, modulo the interval.
+    move_phase: u16,
+    /// The ticks since the last scroll, modulo the interval.
+    wheel_phase: u16,
+    movement: [i8; 4],
+}
+
+impl MouseState {
+    /// Updates the movement of the tick from the held mouse keys.
+    pub(crate) fn tick(&mut self, config: &MouseConfig, keys: impl Iterator<Item = MouseKey>) {
+        let (mut moves, mut wheels) = ((0i8, 0i8), (0i8, 0i8));
+        for key in keys {
+            let (v, (x, y)) = match key {
+                MouseKey::Move(d) => (&mut moves, d.vector()),
+                MouseKey::Wheel(d) => (&mut wheels, d.vector()),
+                MouseKey::Button(_) => continue,
+            };
+            v.0 = (v.0 + x).clamp(-1, 1);
+            v.1 = (v.1 + y).clamp(-1, 1);
+        }
+        self.movement = [0; 4];
+        if moves == (0, 0) {
+            self.move_ticks = 0;
+            self.move_phase = 0;
+        } else {
+            if self.move_phase == 0 {
+                let speed = config.speed(self.move_ticks);
+                self.movement[0] = moves.0 * speed;
+                self.movement[1] = moves.1 * speed;
+            }
+            self.move_ticks = self.move_ticks.saturating_add(1);
+            self.move_phase = (self.move_phase + 1) % config.interval.max(1);
+        }
+        if wheels == (0, 0) {
+            self.wheel_phase = 0;
+        } else {
+            if self.wheel_phase == 0 {
+                let speed = config.wheel_speed.min(i8::MAX as u8) as i8;
+                // The wheel goes up when scrolling up.
+                self.movement[2] = -wheels.1 * speed;
+                self.movement[3] = wheels.0 * speed;
+            }
+            self.wheel_phase = (self.wheel_phase + 1) % config.wheel_interval.max(1);
+        }
+    }
+
+    /// Returns the report with the given buttons and the movement of
+    /// the tick.
+    pub(crate) fn report(&self, buttons: u8) -> MouseReport {
+        let [x, y, wheel, pan] = self.movement;
+        MouseReport([buttons, x as u8, y as u8, wheel as u8, pan as u8])
+    }
+}
+
+/// A mouse USB HID report.
+///
+/// It contains the pressed buttons, the cursor movement, and the
+/// vertical and horizontal wheel movements. The first 3 bytes are
+/// the boot mouse report.
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct MouseReport([u8; 5]);
+
+impl MouseReport {
+    /// Returns the byte slice corresponding to the report.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the pressed buttons, as a bitfield of
+    /// [`MouseButton`].
+    pub fn buttons(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Returns the cursor movement.
+    pub fn movement(&self) -> (i8, i8) {
+        (self.0[1] as i8, self.0[2] as i8)
+    }
+
+    /// Returns the vertical and horizontal wheel movements.
+    pub fn wheel(&self) -> (i8, i8) {
+        (self.0[3] as i8, self.0[4] as i8)
+    }
+
+    /// Returns `true` if there is no movement.
+    pub fn is_still(&self) -> bool {
+        self.0[1..].iter().all(|b| *b == 0)
+    }
+}
+
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+    0x09, 0x02,        // Usage (Mouse)
+    0xA1, 0x01,        // Collection (Application)
+    0x09, 0x01,        //   Usage (Pointer)
+    0xA1, 0x00,        //   Collection (Physical)
+    0x05, 0x09,        //     Usage Page (Button)
+    0x19, 0x01,        //     Usage Minimum (0x01)
+    0x29, 0x05,        //     Usage Maximum (0x05)
+    0x15, 0x00,        //     Logical Minimum (0)
+    0x25, 0x01,        //     Logical Maximum (1)
+    0x95, 0x05,        //     Report Count (5)
+    0x75, 0x01,        //     Report Size (1)
+    0x81, 0x02,        //     Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x95, 0x01,        //     Report Count (1)
+    0x75, 0x03,        //     Report Size (3)
+    0x81, 0x03,        //     Input (Const,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x05, 0x01,        //     Usage Page (Generic Desktop Ctrls)
+    0x09, 0x30,        //     Usage (X)
+    0x09, 0x31,        //     Usage (Y)
+    0x09, 0x38,        //     Usage (Wheel)
+    0x15, 0x81,        //     Logical Minimum (-127)
+    0x25, 0x7F,        //     Logical Maximum (127)
+    0x75, 0x08,        //     Report Size (8)
+    0x95, 0x03,        //     Report Count (3)
+    0x81, 0x06,        //     Input (Data,Var,Rel,No Wrap,Linear,Preferred State,No Null Position)
+    0x05, 0x0C,        //     Usage Page (Consumer)
+    0x0A, 0x38, 0x02,  //     Usage (AC Pan)
+    0x15, 0x81,        //     Logical Minimum (-127)
+    0x25, 0x7F,        //     Logical Maximum (127)
+    0x75, 0x08,        //     Report Size (8)
+    0x95, 0x01,        //     Report Count (1)
+    0x81, 0x06,        //     Input (Data,Var,Rel,No Wrap,Linear,Preferred State,No Null Position)
+    0xC0,              //   End Collection
+    0xC0,              // End Collection
+];
+
+/// A mouse HID device.
+///
+/// It uses the boot mouse report (the buttons and the cursor
+/// movement) when the host selects the boot protocol.
+pub struct Mouse {
+    report: MouseReport,
+    protocol_mode: ProtocolMode,
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Mouse {
+            report: MouseReport::default(),
+            protocol_mode: ProtocolMode::Report,
+        }
+    }
+}
+
+impl Mouse {
+    /// Creates a new `Mouse` object.
+    pub fn new() -> Mouse {
+        Mouse::default()
+    }
+
+    /// Set the current mouse HID report.  Returns `true` if it must
+    /// be sent, i.e. if the buttons are modified or if there is a
+    /// movement.
+    pub fn set_mouse_report(&mut self, report: MouseReport) -> bool {
+        let send = report.buttons() != self.report.buttons() || !report.is_still();
+        self.report = report;
+        send
+    }
+
+    /// Returns the bytes of the report in use, the full report or
+    /// the boot report.
+    pub fn report(&self) -> &[u8] {
+        match self.protocol_mode {
+            ProtocolMode::Report => self.report.as_bytes(),
+            ProtocolMode::Boot => &self.report.as_bytes()[..3],
+        }
+    }
+}
+
+impl HidDevice for Mouse {
+    fn subclass(&self) -> Subclass {
+        Subclass::BootInterface
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::Mouse
+    }
+
+    fn max_packet_size(&self) -> u16 {
+        8
+    }
+
+    fn report_descriptor(&self) -> &[u8] {
+        REPORT_DESCRIPTOR
+    }
+
+    fn get_report(&mut self, report_type: ReportType, _report_id: u8) -> Result<&[u8], hid::Error> {
+        match report_type {
+            ReportType::Input => Ok(self.report()),
+            _ => Err(hid::Error),
+        }
+    }
+
+    fn set_report(
+        &mut self,
+        _report_type: ReportType,
+        _report_id: u8,
+        _data: &[u8],
+    ) -> Result<(), hid::Error> {
+        Err(hid::Error)
+    }
+
+    fn set_protocol_mode(&mut self, mode: ProtocolMode) {
+        self.protocol_mode = mode;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn speed() {
+        let mut config = MouseConfig {
+            delay: 10,
+            time_to_max: 100,
+            initial_speed: 2,
+            max_speed: 12,
+            curve: MouseCurve::Linear,
+            ..MouseConfig::DEFAULT
+        };
+        assert_eq!(config.speed(0), 2);
+        assert_eq!(config.speed(10), 2);
+        assert_eq!(config.speed(60), 7);
+        assert_eq!(config.speed(110), 12);
+        assert_eq!(config.speed(u16::MAX), 12);
+        config.curve = MouseCurve::Quadratic;
+        assert_eq!(config.speed(60), 4);
+        assert_eq!(config.speed(110), 12);
+
+        // no overflow with a long acceleration
+        let config = MouseConfig {
+            delay: 0,
+            time_to_max: 20_000,
+            initial_speed: 1,
+            max_speed: 127,
+            ..MouseConfig::DEFAULT
+        };
+        assert_eq!(config.speed(10_000), 32);
+        assert_eq!(config.speed(19_999), 126);
+        assert_eq!(config.speed(20_000), 127);
+        let config = MouseConfig {
+            curve: MouseCurve::Linear,
+            ..config
+        };
+        assert_eq!(config.speed(10_000), 64);
+    }
+
+    #[test]
+    fn report() {
+        let mut mouse = Mouse::new();
+        let mut state = MouseState::default();
+        let keys = [
+            MouseKey::Move(MouseDirection::Left),
+            MouseKey::Move(MouseDirection::Up),
+            MouseKey::Wheel(MouseDirection::Right),
+        ];
+        state.tick(&MouseConfig::DEFAULT, keys.iter().copied());
+        let report = state.report(MouseButton::Right as u8);
+        assert_eq!(report.as_bytes(), &[0x02, 0xFF, 0xFF, 0, 1]);
+        assert!(mouse.set_mouse_report(report.clone()));
+        assert!(mouse.set_mouse_report(report));
+        assert_eq!(
+            mouse.get_report(ReportType::Input, 0).ok().map(|r| r.len()),
+            Some(5)
+        );
+
+        // no movement on the next tick, nothing to send
+        state.tick(&MouseConfig::DEFAULT, keys.iter().copied());
+        let report = state.report(MouseButton::Right as u8);
+        assert!(report.is_still());
+        assert!(!mouse.set_mouse_report(report));
+
+        mouse.set_protocol_mode(ProtocolMode::Boot);
+        assert_eq!(
+            mouse.get_report(ReportType::Input, 0).ok(),
+            Some(&[0x02, 0, 0][..])
+        );
+    }
+
+    #[test]
+    fn long_hold() {
+        let mut state = MouseState::default();
+        let keys = [
+            MouseKey::Move(MouseDirection::Right),
+            MouseKey::Wheel(MouseDirection::Down),
+        ];
+        // the keys are held for more than u16::MAX ticks
+        for t in 0..70_000u32 {
+            state.tick(&MouseConfig::DEFAULT, keys.iter().copied());
+            if t < 65_000 {
+                continue;
+            }
+            let report = state.report(0);
+            let movement = if t % 16 == 0 { (20, 0) } else { (0, 0) };
+            assert_eq!(report.movement(), movement, "tick {}", t);
+            let wheel = if t % 80 == 0 { (-1, 0) } else { (0, 0) };
+            assert_eq!(report.wheel(), wheel, "tick {}", t);
+        }
+    }
+}
@@ -0,0 +1,679 @@
+//! Persistent settings storage.
+//!
+//! The [`Storage`] trait abstracts a flash-like memory, made of
+//! pages that must be erased before being written. [`Store`] is a
+//! small key-value store on top of it, used to persist the default
+//! layer, the runtime keymap and the feature toggles across resets.
+//! [`RamStorage`] is an in-memory implementation of [`Storage`],
+//! useful for tests.
+//!
+//! The store is a log: the values are appended as records to the
+//! active page, each record being protected by a CRC, so that a
+//! record partially written during a power loss is ignored, the
+//! next records being written on the next page. When the active
+//! page is full, the last value of each key is copied to the next
+//! page, that becomes the active page. The pages are used in turn,
+//! spreading the erasures over all the pages.
+//!
+//! # Example
+//!
+//! ```
+//! use keyberon::storage::{RamStorage, Store};
+//!
+//! let mut store = Store::new(RamStorage::<256, 2>::new()).unwrap();
+//! store.save_default_layer(2).unwrap();
+//! assert_eq!(store.load_default_layer().unwrap(), Some(2));
+//!
+//! // After a reset
+//! let mut store = Store::new(store.release()).unwrap();
+//! assert_eq!(store.load_default_layer().unwrap(), Some(2));
+//! ```
+
+use crate::action::Action;
+use crate::encoding::{self, DecodeError, EncodeError};
+use crate::keymap::RuntimeKeymap;
+
+/// A flash-like memory, made of pages.
+///
+/// An erased page is filled with `0xFF`. A write can only clear
+/// bits, so the bytes must be erased before being written again.
+/// The writes are aligned on 4 bytes, and their length is a
+/// multiple of 4 bytes.
+pub trait Storage {
+    /// The error type of the storage.
+    type Error;
+    /// Returns the size of a page, in bytes. It must be a multiple
+    /// of 4.
+    fn page_size(&self) -> usize;
+    /// Returns the number of pages. There must be at least 2 pages.
+    fn page_count(&self) -> usize;
+    /// Reads `buf.len()` bytes at the given offset.
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+    /// Writes `data` at the given offset.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+    /// Erases the given page.
+    fn erase(&mut self, page: usize) -> Result<(), Self::Error>;
+}
+
+/// An in-memory [`Storage`], of `N` pages of `P` bytes.
+///
+/// It behaves as a flash memory: a write can only clear bits.
+pub struct RamStorage<const P: usize, const N: usize> {
+    pages: [[u8; P]; N],
+    erase_counts: [u32; N],
+}
+
+impl<const P: usize, const N: usize> RamStorage<P, N> {
+    /// Creates a new erased `RamStorage`.
+    pub fn new() -> Self {
+        Self {
+            pages: [[0xFF; P]; N],
+            erase_counts: [0; N],
+        }
+    }
+
+    /// Returns the number of times the given page was erased.
+    pub fn erase_count(&self, page: usize) -> u32 {
+        self.erase_counts[page]
+    }
+}
+
+impl<const P: usize, const N: usize> Default for RamStorage<P, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const P: usize, const N: usize> Storage for RamStorage<P, N> {
+    type Error = core::convert::Infallible;
+
+    fn page_size(&self) -> usize {
+        P
+    }
+    fn page_count(&self) -> usize {
+        N
+    }
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        for (i, b) in buf.iter_mut().enumerate() {
+            let pos = offset + i;
+            *b = self.pages[pos / P][pos % P];
+        }
+        Ok(())
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+        for (i, b) in data.iter().enumerate() {
+            let pos = offset + i;
+            self.pages[pos / P][pos % P] &= b;
+        }
+        Ok(())
+    }
+    fn erase(&mut self, page: usize) -> Result<(), Self::Error> {
+        self.pages[page] = [0xFF; P];
+        self.erase_counts[page] += 1;
+        Ok(())
+    }
+}
+
+/// An error of the store.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error<E> {
+    /// An error of the underlying storage.
+    Storage(E),
+    /// There is no space left to write the value.
+    Full,
+    /// The value is too large to be stored, or to fit in the given
+    /// buffer.
+    TooLarge,
+    /// The key is reserved.
+    InvalidKey,
+    /// The keymap can't be encoded.
+    Encode(EncodeError),
+    /// The stored keymap can't be decoded.
+    Decode(DecodeError),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Storage(e)
+    }
+}
+
+/// The key of the default layer.
+pub const DEFAULT_LAYER_KEY: u16 = 0x0000;
+/// The key of the keymap.
+pub const KEYMAP_KEY: u16 = 0x0001;
+/// The key of the feature toggles.
+pub const FEATURES_KEY: u16 = 0x0002;
+/// The first key free to be used by the firmware.
+pub const USER_KEY: u16 = 0x0100;
+
+/// The key of the erased memory, that can't be used.
+const ERASED_KEY: u16 = 0xFFFF;
+/// The length of a removed value.
+const REMOVED_LEN: u16 = 0xFFFF;
+/// "KBST" in ASCII.
+const MAGIC: u32 = 0x4B42_5354;
+/// The page header: the magic, the sequence number and the CRC of
+/// both.
+const PAGE_HEADER_LEN: usize = 12;
+/// The record header: the key, the length, and the CRC of the key,
+/// the length and the value.
+const RECORD_HEADER_LEN: usize = 8;
+/// The size of the buffer used to read and write the values.
+const CHUNK_LEN: usize = 32;
+
+/// The header of a record.
+#[derive(Clone, Copy)]
+struct Record {
+    key: u16,
+    len: u16,
+    crc: u32,
+}
+
+impl Record {
+    fn data_len(&self) -> usize {
+        if self.len == REMOVED_LEN {
+            0
+        } else {
+            self.len as usize
+        }
+    }
+    /// The size of the record, with the value padded to 4 bytes.
+    fn size(&self) -> usize {
+        RECORD_HEADER_LEN + self.data_len().next_multiple_of(4)
+    }
+    fn to_bytes(self) -> [u8; RECORD_HEADER_LEN] {
+        let mut bytes = [0; RECORD_HEADER_LEN];
+        bytes[..2].copy_from_slice(&self.key.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.len.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+}
+
+/// A key-value store with wear levelling and CRC checked records.
+///
+/// The keys are `u16`, `0xFFFF` being reserved. The keys lower than
+/// [`USER_KEY`] are used by the store helpers (as
+/// [`Store::save_default_layer`]), the other ones are free to be
+/// used by the firmware.
+pub struct Store<S> {
+    storage: S,
+    page: usize,
+    seq: u32,
+    end: usize,
+}
+
+impl<S: Storage> Store<S> {
+    /// Creates a new `Store`, loading the active page of the
+    /// storage, or formatting it if there is none.
+    pub fn new(storage: S) -> Result<Self, Error<S::Error>> {
+        let mut store = Store {
+            storage,
+            page: 0,
+            seq: 0,
+            end: PAGE_HEADER_LEN,
+        };
+        let mut active = None;
+        for page in 0..store.storage.page_count() {
+            if let Some(seq) = store.page_seq(page)? {
+                if !matches!(active, Some((_, s)) if s >= seq) {
+                    active = Some((page, seq));
+                }
+            }
+        }
+        match active {
+            Some((page, seq)) => {
+                store.page = page;
+                store.seq = seq;
+                let mut offset = PAGE_HEADER_LEN;
+                while let Some((_, next)) = store.record(page, offset)? {
+                    offset = next;
+                }
+                store.end = offset;
+                // A write interrupted by a power loss leaves a value
+                // without its header after the last record. The
+                // records are then moved to the next page, the new
+                // records being written on erased memory only.
+                if !store.is_erased(page, offset)? {
+                    let new_page = (page + 1) % store.storage.page_count();
+                    store.end = store.compact(new_page, ERASED_KEY)?;
+                    store.seq = store.seq.wrapping_add(1);
+                    store.write_page_header(new_page, store.seq)?;
+                    store.page = new_page;
+                }
+            }
+            None => {
+                store.storage.erase(0)?;
+                store.write_page_header(0, 0)?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Releases the underlying storage.
+    pub fn release(self) -> S {
+        self.storage
+    }
+
+    /// Reads the value of the given key in `buf`, returning its
+    /// length, or `None` if there is no such value.
+    ///
+    /// Returns [`Error::TooLarge`] if the value doesn't fit in
+    /// `buf`.
+    pub fn read(&mut self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, Error<S::Error>> {
+        let mut found = None;
+        let mut offset = PAGE_HEADER_LEN;
+        while let Some((record, next)) = self.record(self.page, offset)? {
+            if record.key == key && self.is_valid(self.page, offset, record)? {
+                found = Some((offset, record));
+            }
+            offset = next;
+        }
+        match found {
+            Some((_, record)) if record.len == REMOVED_LEN => Ok(None),
+            Some((offset, record)) => {
+                let len = record.data_len();
+                let buf = buf.get_mut(..len).ok_or(Error::TooLarge)?;
+                let offset = self.page * self.storage.page_size() + offset + RECORD_HEADER_LEN;
+                self.storage.read(offset, buf)?;
+                Ok(Some(len))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the value of the given key.
+    pub fn write(&mut self, key: u16, data: &[u8]) -> Result<(), Error<S::Error>> {
+        if data.len() >= REMOVED_LEN as usize {
+            return Err(Error::TooLarge);
+        }
+        self.append(key, Some(data))
+    }
+
+    /// Removes the value of the given key.
+    pub fn remove(&mut self, key: u16) -> Result<(), Error<S::Error>> {
+        self.append(key, None)
+    }
+
+    /// Saves the default layer (see
+    /// [`Layout::set_default_layer`](crate::layout::Layout::set_default_layer)).
+    pub fn save_default_layer(&mut self, layer: usize) -> Result<(), Error<S::Error>> {
+        self.write(DEFAULT_LAYER_KEY, &(layer as u32).to_le_bytes())
+    }
+
+    /// Loads the default layer, if saved.
+    pub fn load_default_layer(&mut self) -> Result<Option<usize>, Error<S::Error>> {
+        Ok(self.read_u32(DEFAULT_LAYER_KEY)?.map(|l| l as usize))
+    }
+
+    /// Saves the feature toggles, a bit field which meaning is
+    /// defined by the firmware.
+    pub fn save_features(&mut self, features: u32) -> Result<(), Error<S::Error>> {
+        self.write(FEATURES_KEY, &features.to_le_bytes())
+    }
+
+    /// Loads the feature toggles, if saved.
+    pub fn load_features(&mut self) -> Result<Option<u32>, Error<S::Error>> {
+        self.read_u32(FEATURES_KEY)
+    }
+
+    /// Saves a keymap, serialized in `buf` (see
+    /// [`encoding::save_keymap`]).
+    pub fn save_keymap<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static>(
+        &mut self,
+        keymap: &RuntimeKeymap<C, R, L, T>,
+        user_actions: &[Action<T>],
+        buf: &mut [u8],
+    ) -> Result<(), Error<S::Error>> {
+        let len = encoding::save_keymap(keymap, user_actions, buf).map_err(Error::Encode)?;
+        self.write(KEYMAP_KEY, &buf[..len])
+    }
+
+    /// Loads the saved keymap in `keymap`, using `buf` to read it
+    /// (see [`encoding::load_keymap`]). Returns `false` if there is
+    /// no saved keymap.
+    pub fn load_keymap<const C: usize, const R: usize, const L: usize, T: 'static>(
+        &mut self,
+        keymap: &mut RuntimeKeymap<C, R, L, T>,
+        user_actions: &'static [Action<T>],
+        buf: &mut [u8],
+    ) -> Result<bool, Error<S::Error>> {
+        match self.read(KEYMAP_KEY, buf)? {
+            Some(len) => {
+                encoding::load_keymap(keymap, user_actions, &buf[..len]).map_err(Error::Decode)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn read_u32(&mut self, key: u16) -> Result<Option<u32>, Error<S::Error>> {
+        let mut buf = [0; 4];
+        match self.read(key, &mut buf) {
+            Ok(Some(4)) => Ok(Some(u32::from_le_bytes(buf))),
+            Ok(_) | Err(Error::TooLarge) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Appends a record, `None` meaning removed.
+    fn append(&mut self, key: u16, data: Option<&[u8]>) -> Result<(), Error<S::Error>> {
+        if key == ERASED_KEY {
+            return Err(Error::InvalidKey);
+        }
+        let len = data.map_or(REMOVED_LEN, |d| d.len() as u16);
+        let data = data.unwrap_or(&[]);
+        let mut record = Record { key, len, crc: 0 };
+        record.crc = crc32(crc32(!0, &record.to_bytes()[..4]), data);
+        let page_size = self.storage.page_size();
+        if record.size() > page_size - PAGE_HEADER_LEN {
+            return Err(Error::TooLarge);
+        }
+        if self.end + record.size() <= page_size {
+            self.write_record(self.page * page_size + self.end, record, data)?;
+            self.end += record.size();
+            return Ok(());
+        }
+        let new_page = (self.page + 1) % self.storage.page_count();
+        let mut end = self.compact(new_page, key)?;
+        if end + record.size() > page_size {
+            return Err(Error::Full);
+        }
+        // The old values of the key are not copied, so a removed
+        // value doesn't need a record.
+        if len != REMOVED_LEN {
+            self.write_record(new_page * page_size + end, record, data)?;
+            end += record.size();
+        }
+        // The header is written last, the new page being used only
+        // if the copy and the write are complete.
+        self.seq = self.seq.wrapping_add(1);
+        self.write_page_header(new_page, self.seq)?;
+        self.page = new_page;
+        self.end = end;
+        Ok(())
+    }
+
+    /// Writes a record at the given offset.
+    fn write_record(&mut self, offset: usize, record: Record, data: &[u8]) -> Result<(), S::Error> {
+        // The value is written before the header, so that a value
+        // partially written is never seen as a complete record.
+        for (i, chunk) in data.chunks(CHUNK_LEN).enumerate() {
+            let mut buf = [0xFF; CHUNK_LEN];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            let len = chunk.len().next_multiple_of(4);
+            let offset = offset + RECORD_HEADER_LEN + i * CHUNK_LEN;
+            self.storage.write(offset, &buf[..len])?;
+        }
+        self.storage.write(offset, &record.to_bytes())
+    }
+
+    /// Copies the last value of each key, except `skipped_key`, to
+    /// `new_page`, returning the end of the copied records.
+    fn compact(&mut self, new_page: usize, skipped_key: u16) -> Result<usize, Error<S::Error>> {
+        let page_size = self.storage.page_size();
+        self.storage.erase(new_page)?;
+        let mut end = PAGE_HEADER_LEN;
+        let mut offset = PAGE_HEADER_LEN;
+        while let Some((record, next)) = self.record(self.page, offset)? {
+            if record.key != skipped_key
+                && record.len != REMOVED_LEN
+                && self.is_valid(self.page, offset, record)?
+                && !self.has_newer(next, record.key)?
+            {
+                if end + record.size() > page_size {
+                    return Err(Error::Full);
+                }
+                let from = self.page * page_size + offset;
+                let to = new_page * page_size + end;
+                let mut buf = [0; CHUNK_LEN];
+                for start in (0..record.size()).step_by(CHUNK_LEN) {
+                    let buf = &mut buf[..(record.size() - start).min(CHUNK_LEN)];
+                    self.storage.read(from + start, buf)?;
+                    self.storage.write(to + start, buf)?;
+                }
+                end += record.size();
+            }
+            offset = next;
+        }
+        Ok(end)
+    }
+
+    /// Returns `true` if there is a valid record of the given key
+    /// from the given offset of the active page.
+    fn has_newer(&mut self, mut offset: usize, key: u16) -> Result<bool, Error<S::Error>> {
+        while let Some((record, next)) = self.record(self.page, offset)? {
+            if record.key == key && self.is_valid(self.page, offset, record)? {
+                return Ok(true);
+            }
+            offset = next;
+        }
+        Ok(false)
+    }
+
+    /// Reads the record at the given offset of the page, returning
+    /// it and the offset of the next record, or `None` if there are
+    /// no more records.
+    fn record(&mut self, page: usize, offset: usize) -> Result<Option<(Record, usize)>, S::Error> {
+        let page_size = self.storage.page_size();
+        if offset + RECORD_HEADER_LEN > page_size {
+            return Ok(None);
+        }
+        let mut buf = [0; RECORD_HEADER_LEN];
+        self.storage.read(page * page_size + offset, &mut buf)?;
+        let record = Record {
+            key: u16::from_le_bytes([buf[0], buf[1]]),
+            len: u16::from_le_bytes([buf[2], buf[3]]),
+            crc: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        };
+        if record.key == ERASED_KEY {
+            return Ok(None);
+        }
+        // A corrupted length fills the page.
+        Ok(Some((record, (offset + record.size()).min(page_size))))
+    }
+
+    /// Checks the CRC of the record at the given offset of the page.
+    fn is_valid(&mut self, page: usize, offset: usize, record: Record) -> Result<bool, S::Error> {
+        let page_size = self.storage.page_size();
+        if offset + record.size() > page_size {
+            return Ok(false);
+        }
+        let mut crc = crc32(!0, &record.to_bytes()[..4]);
+        let mut buf = [0; CHUNK_LEN];
+        let data = page * page_size + offset + RECORD_HEADER_LEN;
+        for start in (0..record.data_len()).step_by(CHUNK_LEN) {
+            let buf = &mut buf[..(record.data_len() - start).min(CHUNK_LEN)];
+            self.storage.read(data + start, buf)?;
+            crc = crc32(crc, buf);
+        }
+        Ok(crc == record.crc)
+    }
+
+    /// Returns `true` if the page is erased from the given offset.
+    fn is_erased(&mut self, page: usize, offset: usize) -> Result<bool, S::Error> {
+        let page_size = self.storage.page_size();
+        let mut buf = [0; CHUNK_LEN];
+        for start in (offset..page_size).step_by(CHUNK_LEN) {
+            let buf = &mut buf[..(page_size - start).min(CHUNK_LEN)];
+            self.storage.read(page * page_size + start, buf)?;
+            if buf.iter().any(|&b| b != 0xFF) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the sequence number of the page, or `None` if it is
+    /// not a valid page.
+    fn page_seq(&mut self, page: usize) -> Result<Option<u32>, S::Error> {
+        let mut buf = [0; PAGE_HEADER_LEN];
+        self.storage
+            .read(page * self.storage.page_size(), &mut buf)?;
+        let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let seq = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let crc = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        Ok((magic == MAGIC && crc == crc32(!0, &buf[..8])).then_some(seq))
+    }
+
+    fn write_page_header(&mut self, page: usize, seq: u32) -> Result<(), S::Error> {
+        let mut buf = [0; PAGE_HEADER_LEN];
+        buf[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..8].copy_from_slice(&seq.to_le_bytes());
+        let crc = crc32(!0, &buf[..8]);
+        buf[8..].copy_from_slice(&crc.to_le_bytes());
+        self.storage.write(page * self.storage.page_size(), &buf)
+    }
+}
+
+/// Updates a CRC-32 (IEEE polynomial) with `data`.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::{k, l};
+    use crate::key_code::KeyCode::*;
+    use crate::keymap::Keymap;
+    use crate::layout::Layers;
+
+    type Ram = RamStorage<128, 4>;
+
+    fn read<S: Storage>(store: &mut Store<S>, key: u16) -> Option<u32> {
+        store.read_u32(key).ok().unwrap()
+    }
+
+    #[test]
+    fn read_write() {
+        let mut store = Store::new(Ram::new()).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(store.read(USER_KEY, &mut buf), Ok(None));
+        store.write(USER_KEY, b"hello").unwrap();
+        store.write(USER_KEY + 1, &42u32.to_le_bytes()).unwrap();
+        assert_eq!(store.read(USER_KEY, &mut buf), Ok(Some(5)));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(store.read(USER_KEY, &mut buf[..4]), Err(Error::TooLarge));
+        store.write(USER_KEY, b"").unwrap();
+        assert_eq!(store.read(USER_KEY, &mut buf), Ok(Some(0)));
+        store.remove(USER_KEY).unwrap();
+        assert_eq!(store.read(USER_KEY, &mut buf), Ok(None));
+        assert_eq!(read(&mut store, USER_KEY + 1), Some(42));
+        assert_eq!(store.write(0xFFFF, b"a"), Err(Error::InvalidKey));
+        assert_eq!(store.write(USER_KEY, &[0; 120]), Err(Error::TooLarge));
+
+        // after a reset
+        let mut store = Store::new(store.release()).unwrap();
+        assert_eq!(store.read(USER_KEY, &mut buf), Ok(None));
+        assert_eq!(read(&mut store, USER_KEY + 1), Some(42));
+    }
+
+    #[test]
+    fn wear_levelling() {
+        let mut store = Store::new(Ram::new()).unwrap();
+        store.write(USER_KEY, b"constant").unwrap();
+        for i in 0..1000u32 {
+            store.write(USER_KEY + 1, &i.to_le_bytes()).unwrap();
+            assert_eq!(read(&mut store, USER_KEY + 1), Some(i));
+        }
+        let mut store = Store::new(store.release()).unwrap();
+        assert_eq!(read(&mut store, USER_KEY + 1), Some(999));
+        let mut buf = [0; 8];
+        assert_eq!(store.read(USER_KEY, &mut buf), Ok(Some(8)));
+        assert_eq!(&buf, b"constant");
+
+        let storage = store.release();
+        let counts: [u32; 4] = core::array::from_fn(|p| storage.erase_count(p));
+        let (min, max) = (counts.iter().min().unwrap(), counts.iter().max().unwrap());
+        assert!(*min > 30, "{:?}", counts);
+        assert!(max - min <= 1, "{:?}", counts);
+    }
+
+    #[test]
+    fn corrupted_record() {
+        let mut store = Store::new(Ram::new()).unwrap();
+        store.write(USER_KEY, &1u32.to_le_bytes()).unwrap();
+        store.write(USER_KEY, &2u32.to_le_bytes()).unwrap();
+        let mut storage = store.release();
+        // clear a bit of the value of the last record
+        let offset = PAGE_HEADER_LEN + 2 * RECORD_HEADER_LEN + 4;
+        storage.write(offset, &[0xFD, 0xFF, 0xFF, 0xFF]).unwrap();
+        let mut store = Store::new(storage).unwrap();
+        assert_eq!(read(&mut store, USER_KEY), Some(1));
+        store.write(USER_KEY, &3u32.to_le_bytes()).unwrap();
+        assert_eq!(read(&mut store, USER_KEY), Some(3));
+    }
+
+    #[test]
+    fn torn_write() {
+        let mut store = Store::new(Ram::new()).unwrap();
+        store.write(USER_KEY, &1u32.to_le_bytes()).unwrap();
+        let mut storage = store.release();
+        // the value of a second record is written, but not its
+        // header
+        let offset = PAGE_HEADER_LEN + 2 * RECORD_HEADER_LEN + 4;
+        storage.write(offset, &2u32.to_le_bytes()).unwrap();
+        let mut store = Store::new(storage).unwrap();
+        assert_eq!(read(&mut store, USER_KEY), Some(1));
+        store.write(USER_KEY, &7u32.to_le_bytes()).unwrap();
+        assert_eq!(read(&mut store, USER_KEY), Some(7));
+
+        // after a reset
+        let mut store = Store::new(store.release()).unwrap();
+        assert_eq!(read(&mut store, USER_KEY), Some(7));
+        store.write(USER_KEY + 1, &8u32.to_le_bytes()).unwrap();
+        assert_eq!(read(&mut store, USER_KEY), Some(7));
+        assert_eq!(read(&mut store, USER_KEY + 1), Some(8));
+    }
+
+    #[test]
+    fn full() {
+        let mut store = Store::new(RamStorage::<64, 2>::new()).unwrap();
+        for key in 0..4 {
+            store.write(USER_KEY + key, &[0; 4]).unwrap();
+        }
+        assert_eq!(store.write(USER_KEY + 4, &[0; 4]), Err(Error::Full));
+        store.remove(USER_KEY).unwrap();
+        store.write(USER_KEY + 4, &[0; 4]).unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(store.read(USER_KEY + 3, &mut buf), Ok(Some(4)));
+    }
+
+    #[test]
+    fn settings() {
+        static LAYERS: Layers<2, 1, 2> = [[[k(A), l(1)]], [[k(B), k(C)]]];
+        static USER_ACTIONS: [crate::action::Action; 0] = [];
+        let mut store = Store::new(RamStorage::<256, 2>::new()).unwrap();
+        let mut keymap = RuntimeKeymap::new(&LAYERS);
+        let mut buf = [0; 32];
+        assert_eq!(store.load_default_layer(), Ok(None));
+        assert_eq!(store.load_features(), Ok(None));
+        assert_eq!(
+            store.load_keymap(&mut keymap, &USER_ACTIONS, &mut buf),
+            Ok(false)
+        );
+
+        store.save_default_layer(1).unwrap();
+        store.save_features(0b101).unwrap();
+        keymap.set_action(1, 0, 1, &crate::action::Action::KeyCode(D));
+        store.save_keymap(&keymap, &USER_ACTIONS, &mut buf).unwrap();
+
+        let mut store = Store::new(store.release()).unwrap();
+        let mut keymap = RuntimeKeymap::new(&LAYERS);
+        assert_eq!(store.load_default_layer(), Ok(Some(1)));
+        assert_eq!(store.load_features(), Ok(Some(0b101)));
+        assert_eq!(
+            store.load_keymap(&mut keymap, &USER_ACTIONS, &mut buf),
+            Ok(true)
+        );
+        assert_eq!(keymap.action(1, 0, 1), Some(&k(D)));
+    }
+}
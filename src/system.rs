@@ -0,0 +1,149 @@
+//! System control HID device implementation.
+//!
+//! The system power down, sleep and wake up controls are sent on the
+//! generic desktop usage page, using a [`SystemControl`] HID device
+//! alongside the [`Keyboard`](crate::keyboard::Keyboard). The
+//! [`Layout`](crate::layout::Layout) gives the pressed system codes
+//! with [`Layout::system_codes`](crate::layout::Layout::system_codes).
+
+use crate::hid::{self, HidDevice, Protocol, ReportType, Subclass};
+
+/// A system control code, i.e. a system control usage of the
+/// generic desktop usage page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum SystemCode {
+    /// System Power Down.
+    PowerDown = 0x81,
+    /// System Sleep.
+    Sleep = 0x82,
+    /// System Wake Up.
+    WakeUp = 0x83,
+}
+
+/// A system control USB HID report.
+///
+/// It is a bitfield of the pressed system codes.
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct SystemReport([u8; 1]);
+
+impl core::iter::FromIterator<SystemCode> for SystemReport {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = SystemCode>,
+    {
+        let mut res = Self::default();
+        for sc in iter {
+            res.pressed(sc);
+        }
+        res
+    }
+}
+
+impl SystemReport {
+    /// Returns the byte slice corresponding to the report.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Add the given system code to the report.
+    pub fn pressed(&mut self, sc: SystemCode) {
+        self.0[0] |= 1 << (sc as u8 - SystemCode::PowerDown as u8);
+    }
+}
+
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+    0x09, 0x80,        // Usage (Sys Control)
+    0xA1, 0x01,        // Collection (Application)
+    0x19, 0x81,        //   Usage Minimum (Sys Power Down)
+    0x29, 0x83,        //   Usage Maximum (Sys Wake Up)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x25, 0x01,        //   Logical Maximum (1)
+    0x95, 0x03,        //   Report Count (3)
+    0x75, 0x01,        //   Report Size (1)
+    0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x95, 0x01,        //   Report Count (1)
+    0x75, 0x05,        //   Report Size (5)
+    0x81, 0x03,        //   Input (Const,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0xC0,              // End Collection
+];
+
+/// A system control HID device.
+#[derive(Default)]
+pub struct SystemControl {
+    report: SystemReport,
+}
+
+impl SystemControl {
+    /// Creates a new `SystemControl` object.
+    pub fn new() -> SystemControl {
+        SystemControl::default()
+    }
+
+    /// Set the current system HID report.  Returns `true` if it is
+    /// modified.
+    pub fn set_system_report(&mut self, report: SystemReport) -> bool {
+        if report == self.report {
+            false
+        } else {
+            self.report = report;
+            true
+        }
+    }
+}
+
+impl HidDevice for SystemControl {
+    fn subclass(&self) -> Subclass {
+        Subclass::None
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::None
+    }
+
+    fn max_packet_size(&self) -> u16 {
+        8
+    }
+
+    fn report_descriptor(&self) -> &[u8] {
+        REPORT_DESCRIPTOR
+    }
+
+    fn get_report(&mut self, report_type: ReportType, _report_id: u8) -> Result<&[u8], hid::Error> {
+        match report_type {
+            ReportType::Input => Ok(self.report.as_bytes()),
+            _ => Err(hid::Error),
+        }
+    }
+
+    fn set_report(
+        &mut self,
+        _report_type: ReportType,
+        _report_id: u8,
+        _data: &[u8],
+    ) -> Result<(), hid::Error> {
+        Err(hid::Error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut system = SystemControl::new();
+        assert_eq!(system.get_report(ReportType::Input, 0).ok(), Some(&[0][..]));
+        let report: SystemReport = [SystemCode::Sleep].iter().copied().collect();
+        assert_eq!(report.as_bytes(), &[0b010]);
+        assert!(system.set_system_report(report.clone()));
+        assert!(!system.set_system_report(report));
+        let report: SystemReport = [SystemCode::WakeUp, SystemCode::PowerDown]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(report.as_bytes(), &[0b101]);
+    }
+}
@@ -0,0 +1,488 @@
+//! VIA raw HID configuration protocol.
+//!
+//! The [`Via`] HID device allows to remap the keys from the host
+//! using the [VIA](https://caniusevia.com/) configuration tools. It
+//! uses a vendor defined usage page, on which the host sends 32
+//! bytes commands as output reports. The response to a command is
+//! sent on the interrupt endpoint by the
+//! [`HidClass`](crate::hid::HidClass), and can also be read as an
+//! input report.
+//!
+//! The supported commands are: get protocol version, get and set
+//! the keycode of a key, get the layer count, read and write the
+//! dynamic keymap buffer, and reset the keymap. The keyboard values
+//! and the macros are answered as if the keyboard had no layout
+//! options, no macros, and a firmware version of 0. The unsupported
+//! commands are answered with `0xFF` as the command id.
+//!
+//! The keycodes are encoded using the [`encoding`](crate::encoding)
+//! module. The commands are received in the USB interrupt, but are
+//! handled by [`Via::process`] on the keymap of the layout, usually
+//! in the main loop:
+//!
+//! ```
+//! use keyberon::action::{k, Action};
+//! use keyberon::key_code::KeyCode;
+//! use keyberon::layout::{Layers, Layout};
+//! use keyberon::via::Via;
+//!
+//! static LAYERS: Layers<2, 1, 1> = [[[k(KeyCode::A), k(KeyCode::B)]]];
+//! static USER_ACTIONS: [Action; 0] = [];
+//! let mut via = Via::new(&LAYERS, &USER_ACTIONS);
+//! let mut layout = Layout::new_runtime(&LAYERS);
+//!
+//! // In the USB interrupt: the HID class calls `set_report`, and
+//! // then writes the response.
+//! // In the main loop, with `via_class.device_mut()` as `via`:
+//! if via.process(layout.keymap_mut()) {
+//!     // The keymap can be saved.
+//! }
+//! ```
+
+use crate::action::Action;
+use crate::encoding::ActionCode;
+use crate::hid::{self, HidDevice, Protocol, ReportType, Subclass};
+use crate::keymap::{Keymap, RuntimeKeymap};
+use crate::layout::Layers;
+
+/// The length of the VIA reports.
+pub const REPORT_LEN: usize = 32;
+
+/// The version of the VIA protocol implemented.
+pub const PROTOCOL_VERSION: u16 = 0x000C;
+
+const GET_PROTOCOL_VERSION: u8 = 0x01;
+const GET_KEYBOARD_VALUE: u8 = 0x02;
+const SET_KEYBOARD_VALUE: u8 = 0x03;
+const DYNAMIC_KEYMAP_GET_KEYCODE: u8 = 0x04;
+const DYNAMIC_KEYMAP_SET_KEYCODE: u8 = 0x05;
+const DYNAMIC_KEYMAP_RESET: u8 = 0x06;
+const DYNAMIC_KEYMAP_MACRO_GET_COUNT: u8 = 0x0C;
+const DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE: u8 = 0x0D;
+const DYNAMIC_KEYMAP_MACRO_GET_BUFFER: u8 = 0x0E;
+const DYNAMIC_KEYMAP_MACRO_SET_BUFFER: u8 = 0x0F;
+const DYNAMIC_KEYMAP_MACRO_RESET: u8 = 0x10;
+const DYNAMIC_KEYMAP_GET_LAYER_COUNT: u8 = 0x11;
+const DYNAMIC_KEYMAP_GET_BUFFER: u8 = 0x12;
+const DYNAMIC_KEYMAP_SET_BUFFER: u8 = 0x13;
+const UNHANDLED: u8 = 0xFF;
+
+// The keyboard values.
+const LAYOUT_OPTIONS: u8 = 0x02;
+const FIRMWARE_VERSION: u8 = 0x04;
+
+/// The maximum size of the data in a buffer command.
+const BUFFER_MAX_SIZE: usize = REPORT_LEN - 4;
+
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0x60, 0xFF,  // Usage Page (Vendor Defined 0xFF60)
+    0x09, 0x61,        // Usage (0x61)
+    0xA1, 0x01,        // Collection (Application)
+    0x09, 0x62,        //   Usage (0x62)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x26, 0xFF, 0x00,  //   Logical Maximum (255)
+    0x95, 0x20,        //   Report Count (32)
+    0x75, 0x08,        //   Report Size (8)
+    0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x09, 0x63,        //   Usage (0x63)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x26, 0xFF, 0x00,  //   Logical Maximum (255)
+    0x95, 0x20,        //   Report Count (32)
+    0x75, 0x08,        //   Report Size (8)
+    0x91, 0x02,        //   Output (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position,Non-volatile)
+    0xC0,              // End Collection
+];
+
+/// A VIA raw HID device.
+///
+/// The generic parameters are in order: the number of columns, rows
+/// and layers, and the type contained in custom actions.
+///
+/// The actions that have no dedicated keycode are encoded as user
+/// keycodes, using the table of user actions (see
+/// [`ActionCode`]). The actions that can't be encoded are reported
+/// as `0x0000`, and the unknown keycodes sent by the host are
+/// ignored.
+///
+/// The host waits for the response to a command before sending the
+/// next one: a command received before the previous one is
+/// processed replaces it.
+pub struct Via<
+    const C: usize,
+    const R: usize,
+    const L: usize,
+    T: 'static = core::convert::Infallible,
+> {
+    default_layers: &'static Layers<C, R, L, T>,
+    user_actions: &'static [Action<T>],
+    report: [u8; REPORT_LEN],
+    command_pending: bool,
+    response_pending: bool,
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static> Via<C, R, L, T> {
+    /// The layer count reported to the host, on one byte.
+    const LAYER_COUNT: u8 = {
+        assert!(L <= u8::MAX as usize, "VIA supports at most 255 layers");
+        L as u8
+    };
+
+    /// Creates a new `Via` object. The keymap is reset to the given
+    /// layers on the reset command.
+    pub fn new(
+        default_layers: &'static Layers<C, R, L, T>,
+        user_actions: &'static [Action<T>],
+    ) -> Self {
+        Self {
+            default_layers,
+            user_actions,
+            report: [0; REPORT_LEN],
+            command_pending: false,
+            response_pending: false,
+        }
+    }
+
+    /// Handles the command received from the host, if any, on the
+    /// given keymap. Returns `true` if the keymap has been modified.
+    ///
+    /// The response is then available with [`Via::take_response`].
+    pub fn process(&mut self, keymap: &mut RuntimeKeymap<C, R, L, T>) -> bool {
+        if !core::mem::take(&mut self.command_pending) {
+            return false;
+        }
+        let modified = self.command(keymap);
+        self.response_pending = true;
+        modified
+    }
+
+    /// Returns the response to the last processed command, once per
+    /// command. The [`HidClass`](crate::hid::HidClass) writes it on
+    /// the interrupt endpoint.
+    pub fn take_response(&mut self) -> Option<&[u8; REPORT_LEN]> {
+        if core::mem::take(&mut self.response_pending) {
+            Some(&self.report)
+        } else {
+            None
+        }
+    }
+
+    /// Handles a command, the response being stored in the report.
+    /// Returns `true` if the keymap has been modified.
+    fn command(&mut self, keymap: &mut RuntimeKeymap<C, R, L, T>) -> bool {
+        let r = &mut self.report;
+        let user_actions = self.user_actions;
+        match r[0] {
+            GET_PROTOCOL_VERSION => r[1..3].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes()),
+            GET_KEYBOARD_VALUE => match r[1] {
+                LAYOUT_OPTIONS | FIRMWARE_VERSION => r[2..6].copy_from_slice(&[0; 4]),
+                _ => r[0] = UNHANDLED,
+            },
+            SET_KEYBOARD_VALUE => match r[1] {
+                LAYOUT_OPTIONS => (),
+                _ => r[0] = UNHANDLED,
+            },
+            DYNAMIC_KEYMAP_GET_KEYCODE => {
+                let (layer, row, col) = (r[1] as usize, r[2] as usize, r[3] as usize);
+                let code = keycode(keymap, user_actions, layer, row, col);
+                r[4..6].copy_from_slice(&code.to_be_bytes());
+            }
+            DYNAMIC_KEYMAP_SET_KEYCODE => {
+                let (layer, row, col) = (r[1] as usize, r[2] as usize, r[3] as usize);
+                let code = u16::from_be_bytes([r[4], r[5]]);
+                return set_keycode(keymap, user_actions, layer, row, col, code);
+            }
+            DYNAMIC_KEYMAP_RESET => {
+                *keymap = RuntimeKeymap::new(self.default_layers);
+                return true;
+            }
+            DYNAMIC_KEYMAP_MACRO_GET_COUNT => r[1] = 0,
+            DYNAMIC_KEYMAP_MACRO_GET_BUFFER_SIZE => r[1..3].copy_from_slice(&[0; 2]),
+            DYNAMIC_KEYMAP_MACRO_GET_BUFFER => r[4..].copy_from_slice(&[0; BUFFER_MAX_SIZE]),
+            DYNAMIC_KEYMAP_MACRO_SET_BUFFER | DYNAMIC_KEYMAP_MACRO_RESET => (),
+            DYNAMIC_KEYMAP_GET_LAYER_COUNT => r[1] = Self::LAYER_COUNT,
+            DYNAMIC_KEYMAP_GET_BUFFER => {
+                let offset = u16::from_be_bytes([r[1], r[2]]) as usize;
+                let size = (r[3] as usize).min(BUFFER_MAX_SIZE);
+                for i in 0..size {
+                    let pos = offset + i;
+                    let code = buffer_keycode(keymap, user_actions, pos / 2);
+                    r[4 + i] = code.to_be_bytes()[pos % 2];
+                }
+            }
+            DYNAMIC_KEYMAP_SET_BUFFER => {
+                let offset = u16::from_be_bytes([r[1], r[2]]) as usize;
+                let size = (r[3] as usize).min(BUFFER_MAX_SIZE);
+                let data = &r[4..];
+                let (mut pos, end) = (offset, offset + size);
+                let mut modified = false;
+                while pos < end {
+                    // a keycode may be written in two commands
+                    let key = pos / 2;
+                    let mut bytes = buffer_keycode(keymap, user_actions, key).to_be_bytes();
+                    while pos < end && pos / 2 == key {
+                        bytes[pos % 2] = data[pos - offset];
+                        pos += 1;
+                    }
+                    let (layer, row, col) = (key / (R * C), key / C % R, key % C);
+                    let code = u16::from_be_bytes(bytes);
+                    modified |= set_keycode(keymap, user_actions, layer, row, col, code);
+                }
+                return modified;
+            }
+            _ => r[0] = UNHANDLED,
+        }
+        false
+    }
+}
+
+/// Returns the keycode of the key at the given index of the dynamic
+/// keymap buffer.
+fn buffer_keycode<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static>(
+    keymap: &RuntimeKeymap<C, R, L, T>,
+    user_actions: &[Action<T>],
+    key: usize,
+) -> u16 {
+    keycode(keymap, user_actions, key / (R * C), key / C % R, key % C)
+}
+
+fn keycode<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static>(
+    keymap: &RuntimeKeymap<C, R, L, T>,
+    user_actions: &[Action<T>],
+    layer: usize,
+    row: usize,
+    col: usize,
+) -> u16 {
+    keymap
+        .action(layer, row, col)
+        .and_then(|a| ActionCode::from_action(a, user_actions))
+        .and_then(ActionCode::encode)
+        .unwrap_or(0)
+}
+
+/// Sets the keycode of a key, returning `true` if the keymap has
+/// been modified.
+fn set_keycode<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static>(
+    keymap: &mut RuntimeKeymap<C, R, L, T>,
+    user_actions: &'static [Action<T>],
+    layer: usize,
+    row: usize,
+    col: usize,
+    code: u16,
+) -> bool {
+    keymap.set_code(layer, row, col, code, user_actions).is_ok()
+}
+
+impl<const C: usize, const R: usize, const L: usize, T: PartialEq + 'static> HidDevice
+    for Via<C, R, L, T>
+{
+    fn subclass(&self) -> Subclass {
+        Subclass::None
+    }
+
+    fn protocol(&self) -> Protocol {
+        Protocol::None
+    }
+
+    fn max_packet_size(&self) -> u16 {
+        REPORT_LEN as u16
+    }
+
+    fn report_descriptor(&self) -> &[u8] {
+        REPORT_DESCRIPTOR
+    }
+
+    fn get_report(&mut self, report_type: ReportType, _report_id: u8) -> Result<&[u8], hid::Error> {
+        match report_type {
+            ReportType::Input => Ok(&self.report),
+            _ => Err(hid::Error),
+        }
+    }
+
+    fn set_report(
+        &mut self,
+        report_type: ReportType,
+        report_id: u8,
+        data: &[u8],
+    ) -> Result<(), hid::Error> {
+        if report_type != ReportType::Output
+            || report_id != 0
+            || data.is_empty()
+            || data.len() > REPORT_LEN
+        {
+            return Err(hid::Error);
+        }
+        self.report = [0; REPORT_LEN];
+        self.report[..data.len()].copy_from_slice(data);
+        self.command_pending = true;
+        self.response_pending = false;
+        Ok(())
+    }
+
+    fn pending_input_report(&self) -> Option<&[u8]> {
+        self.response_pending.then_some(&self.report[..])
+    }
+
+    fn input_report_sent(&mut self) {
+        self.response_pending = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::action::{k, l};
+    use crate::key_code::KeyCode::*;
+
+    static LAYERS: Layers<2, 1, 2> = [[[k(A), l(1)]], [[k(B), Action::Trans]]];
+    static USER_ACTIONS: [Action; 0] = [];
+
+    /// Runs a command, returning the response and whether the keymap
+    /// has been modified.
+    fn run(
+        via: &mut Via<2, 1, 2>,
+        keymap: &mut RuntimeKeymap<2, 1, 2>,
+        data: &[u8],
+    ) -> ([u8; REPORT_LEN], bool) {
+        assert!(via.set_report(ReportType::Output, 0, data).is_ok());
+        assert!(via.take_response().is_none());
+        let modified = via.process(keymap);
+        let response = *via.take_response().unwrap();
+        assert!(via.take_response().is_none());
+        assert_eq!(
+            via.get_report(ReportType::Input, 0).ok(),
+            Some(&response[..])
+        );
+        (response, modified)
+    }
+
+    fn command(
+        via: &mut Via<2, 1, 2>,
+        keymap: &mut RuntimeKeymap<2, 1, 2>,
+        data: &[u8],
+    ) -> [u8; REPORT_LEN] {
+        let (response, modified) = run(via, keymap, data);
+        assert!(!modified);
+        response
+    }
+
+    #[test]
+    fn protocol_version_and_layers() {
+        let mut via = Via::new(&LAYERS, &USER_ACTIONS);
+        let mut keymap = RuntimeKeymap::new(&LAYERS);
+        assert!(!via.process(&mut keymap));
+        assert!(via.take_response().is_none());
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x01])[..3],
+            [0x01, 0x00, 0x0C]
+        );
+        assert_eq!(command(&mut via, &mut keymap, &[0x11])[..2], [0x11, 2]);
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x42, 1, 2])[..3],
+            [0xFF, 1, 2]
+        );
+        assert!(via.set_report(ReportType::Feature, 0, &[0x01]).is_err());
+        assert!(via.set_report(ReportType::Output, 0, &[0; 33]).is_err());
+    }
+
+    #[test]
+    fn keyboard_values_and_macros() {
+        let mut via = Via::new(&LAYERS, &USER_ACTIONS);
+        let mut keymap = RuntimeKeymap::new(&LAYERS);
+        // layout options and firmware version
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x02, 0x02, 1, 2, 3, 4])[..6],
+            [0x02, 0x02, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x02, 0x04, 1, 2, 3, 4])[..6],
+            [0x02, 0x04, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x02, 0x01])[..2],
+            [0xFF, 0x01]
+        );
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x03, 0x02, 0, 0, 0, 1])[..6],
+            [0x03, 0x02, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x03, 0x05])[..2],
+            [0xFF, 0x05]
+        );
+
+        // no macros
+        assert_eq!(command(&mut via, &mut keymap, &[0x0C, 9])[..2], [0x0C, 0]);
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x0D, 9, 9])[..3],
+            [0x0D, 0, 0]
+        );
+        let mut get = [0xFF; REPORT_LEN];
+        get[..4].copy_from_slice(&[0x0E, 0, 0, 28]);
+        assert_eq!(command(&mut via, &mut keymap, &get)[4..], [0; 28]);
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x0F, 0, 0, 1, 5])[..5],
+            [0x0F, 0, 0, 1, 5]
+        );
+        assert_eq!(command(&mut via, &mut keymap, &[0x10])[..1], [0x10]);
+    }
+
+    #[test]
+    fn keycodes() {
+        let mut via = Via::new(&LAYERS, &USER_ACTIONS);
+        let mut keymap = RuntimeKeymap::new(&LAYERS);
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x04, 0, 0, 1])[..6],
+            [0x04, 0, 0, 1, 0x52, 0x21]
+        );
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x04, 1, 0, 0])[..6],
+            [0x04, 1, 0, 0, 0x00, 0x05]
+        );
+
+        let (response, modified) = run(&mut via, &mut keymap, &[0x05, 1, 0, 1, 0x00, 0x06]);
+        assert_eq!(response[..6], [0x05, 1, 0, 1, 0x00, 0x06]);
+        assert!(modified);
+        assert_eq!(keymap.action(1, 0, 1), Some(&k(C)));
+        assert_eq!(
+            command(&mut via, &mut keymap, &[0x04, 1, 0, 1])[4..6],
+            [0x00, 0x06]
+        );
+
+        // unknown keycodes and keys are ignored
+        command(&mut via, &mut keymap, &[0x05, 1, 0, 1, 0x12, 0x34]);
+        command(&mut via, &mut keymap, &[0x05, 2, 0, 0, 0x00, 0x06]);
+        assert_eq!(keymap.action(1, 0, 1), Some(&k(C)));
+
+        assert!(run(&mut via, &mut keymap, &[0x06]).1);
+        assert_eq!(keymap.action(1, 0, 1), Some(&Action::Trans));
+    }
+
+    #[test]
+    fn buffer() {
+        let mut via = Via::new(&LAYERS, &USER_ACTIONS);
+        let mut layout = crate::layout::Layout::new_runtime(&LAYERS);
+        let report = command(&mut via, layout.keymap_mut(), &[0x12, 0, 0, 8]);
+        assert_eq!(report[..4], [0x12, 0, 0, 8]);
+        assert_eq!(
+            report[4..12],
+            [0x00, 0x04, 0x52, 0x21, 0x00, 0x05, 0x00, 0x01]
+        );
+        // out of the keymap
+        assert_eq!(
+            command(&mut via, layout.keymap_mut(), &[0x12, 0, 6, 4])[4..8],
+            [0x00, 0x01, 0, 0]
+        );
+
+        // a keycode split between two commands
+        assert!(run(&mut via, layout.keymap_mut(), &[0x13, 0, 1, 2, 0x07, 0x00]).1);
+        assert_eq!(layout.keymap().action(0, 0, 0), Some(&k(D)));
+        assert_eq!(layout.keymap().action(0, 0, 1), Some(&k(Kb4)));
+        assert!(run(&mut via, layout.keymap_mut(), &[0x13, 0, 3, 1, 0x08]).1);
+        assert_eq!(layout.keymap().action(0, 0, 1), Some(&k(E)));
+        assert_eq!(
+            command(&mut via, layout.keymap_mut(), &[0x12, 0, 0, 4])[4..8],
+            [0x00, 0x07, 0x00, 0x08]
+        );
+    }
+}